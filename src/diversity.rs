@@ -0,0 +1,109 @@
+use crate::genome::Genome;
+use std::collections::{HashMap, HashSet};
+
+//How different the genomes of a population are from each other.
+//High selection pressure (e.g. a high tournament_k) fills the population with copies of the same genome, which shows up here.
+#[derive(Clone, Debug, Default)]
+pub struct Diversity {
+    pub population_size: usize,
+    pub gene_count: usize,
+    pub unique_genomes: usize,
+    pub mean_distance: f64, //Mean distance between every pair of genomes, in genes
+    pub entropy: f64, //Shannon entropy of every gene over the population, averaged over all genes, in bits
+}
+
+impl Diversity {
+    pub fn measure<G: Genome>(population: &[G]) -> Self {
+        let genes = population.iter().map(|g| g.genes()).collect::<Vec<_>>();
+        let gene_count = genes.iter().map(|g| g.len()).max().unwrap_or(0);
+
+        let unique_genomes = population.iter().collect::<HashSet<_>>().len();
+
+        let mut distance_sum = 0.;
+        let mut pairs = 0;
+        for (i, a) in population.iter().enumerate() {
+            for b in &population[i + 1..] {
+                distance_sum += a.distance(b);
+                pairs += 1;
+            }
+        }
+        let mean_distance = if pairs > 0 {
+            distance_sum / pairs as f64
+        } else {
+            0.
+        };
+
+        let entropy = if gene_count > 0 {
+            (0..gene_count)
+                .map(|i| entropy(genes.iter().filter_map(|g| g.get(i).copied())))
+                .sum::<f64>()
+                / gene_count as f64
+        } else {
+            0.
+        };
+
+        Diversity {
+            population_size: population.len(),
+            gene_count,
+            unique_genomes,
+            mean_distance,
+            entropy,
+        }
+    }
+
+    //1 if every genome is different, 1/population_size if they're all the same
+    pub fn unique_share(&self) -> f64 {
+        self.unique_genomes as f64 / self.population_size.max(1) as f64
+    }
+
+    //Mean distance as a fraction of the genes
+    pub fn relative_distance(&self) -> f64 {
+        self.mean_distance / self.gene_count.max(1) as f64
+    }
+}
+
+fn entropy(values: impl Iterator<Item = u32>) -> f64 {
+    let mut counts = HashMap::new();
+    let mut total = 0;
+    for value in values {
+        *counts.entry(value).or_insert(0) += 1;
+        total += 1;
+    }
+
+    counts
+        .values()
+        .map(|count| {
+            let p = *count as f64 / total as f64;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use crate::vehicle::{Block, Vehicle};
+
+    let wheels = Vehicle::new_fill_with(Block::Wheel);
+    let panels = Vehicle::new_fill_with(Block::Panel);
+    let air = Vehicle::new_fill_with(Block::Air);
+
+    //Every gene is a wheel half of the time and a panel or air a quarter of the time each: 1.5 bits
+    let diversity = Diversity::measure(&[wheels.clone(), wheels.clone(), panels, air]);
+    assert_eq!(diversity.population_size, 4);
+    assert_eq!(diversity.gene_count, 48);
+    assert_eq!(diversity.unique_genomes, 3);
+    assert_eq!(diversity.mean_distance, 40.); //5 of the 6 pairs differ in all 48 genes
+    assert_eq!(diversity.entropy, 1.5);
+    assert_eq!(diversity.unique_share(), 0.75);
+    assert_eq!(diversity.relative_distance(), 40. / 48.);
+
+    //Copies of the same genome aren't diverse at all
+    let diversity = Diversity::measure(&vec![wheels; 3]);
+    assert_eq!(diversity.unique_share(), 1. / 3.);
+    assert_eq!((diversity.mean_distance, diversity.entropy), (0., 0.));
+
+    let empty = Diversity::measure::<Vehicle>(&[]);
+    assert_eq!((empty.population_size, empty.gene_count), (0, 0));
+    assert_eq!((empty.unique_share(), empty.relative_distance()), (0., 0.));
+}
@@ -0,0 +1,274 @@
+use crate::{
+    novelty::{Behavior, Descriptor},
+    track::TrackSet,
+    vehicle::Vehicle,
+};
+#[cfg(feature = "app")]
+use bevy_inspector_egui::Inspectable;
+use dashmap::DashMap;
+use std::sync::Arc;
+
+//Every genome that was evaluated so far, and its fitness
+pub type GlobalFitnessMap<G = Vehicle> = Arc<DashMap<G, FitnessRecord>>;
+
+//Which statistic over all evaluations of a vehicle is used as its fitness during selection
+#[cfg_attr(feature = "app", derive(Inspectable))]
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum FitnessStatistic {
+    Mean,
+    Min, //Pessimistic, punishes vehicles that only got lucky once
+    Max,
+}
+
+//What to do when a vehicle shows up that has been simulated before
+#[cfg_attr(feature = "app", derive(Inspectable))]
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum CachePolicy {
+    AlwaysReuse,            //Never simulate a vehicle twice
+    ReevaluateAndAverage,   //Simulate it again, the new fitness gets added to its record
+    ExpireAfterGenerations, //Reuse the fitness, unless it's older than cache_expiry_generations
+}
+
+impl CachePolicy {
+    //Whether the record can be used instead of evaluating the genome again
+    pub fn reuses(self, record: &FitnessRecord, generation: usize, expiry: usize) -> bool {
+        match self {
+            CachePolicy::AlwaysReuse => true,
+            CachePolicy::ReevaluateAndAverage => false,
+            CachePolicy::ExpireAfterGenerations => !self.expired(record, generation, expiry),
+        }
+    }
+
+    //Whether the samples of the record are too old to be used at all, not even averaged with new ones
+    pub fn expired(self, record: &FitnessRecord, generation: usize, expiry: usize) -> bool {
+        self == CachePolicy::ExpireAfterGenerations
+            && generation.saturating_sub(record.last_generation()) >= expiry
+    }
+}
+
+//All fitness values a vehicle got so far
+#[derive(Default, Clone, Debug)]
+pub struct FitnessRecord {
+    samples: Vec<i64>,
+    tracks: Vec<usize>, //Index of the track in the track set every sample was measured on
+    last_generation: usize, //Generation in which the last sample was added
+    behaviors: Vec<Behavior>, //Of every evaluation that recorded one, for novelty search and multi-objective evolution
+}
+
+impl FitnessRecord {
+    pub fn push(&mut self, track: usize, fitness: i64, generation: usize) {
+        self.samples.push(fitness);
+        self.tracks.push(track);
+        self.last_generation = generation;
+    }
+
+    pub fn push_behavior(&mut self, behavior: &Behavior) {
+        self.behaviors.push(*behavior);
+    }
+
+    //Average behavior over all evaluations, None if no behavior was recorded
+    pub fn descriptor(&self) -> Option<Descriptor> {
+        if self.behaviors.is_empty() {
+            return None;
+        }
+        let mut mean = [0.; 4];
+        for behavior in &self.behaviors {
+            for (m, x) in mean.iter_mut().zip(&behavior.descriptor()) {
+                *m += x / self.behaviors.len() as f64;
+            }
+        }
+        Some(mean)
+    }
+
+    //Average distance from the spawn point, unlike the fitness without the punishment for falling apart
+    pub fn distance(&self) -> Option<f64> {
+        if self.behaviors.is_empty() {
+            return None;
+        }
+        let sum = self
+            .behaviors
+            .iter()
+            .map(|b| b.distance as f64)
+            .sum::<f64>();
+        Some(sum / self.behaviors.len() as f64)
+    }
+
+    //Fraction of the evaluations in which the vehicle stayed in one piece
+    pub fn integrity(&self) -> Option<f64> {
+        if self.behaviors.is_empty() {
+            return None;
+        }
+        let intact = self.behaviors.iter().filter(|b| !b.fell_apart).count();
+        Some(intact as f64 / self.behaviors.len() as f64)
+    }
+
+    //Only the samples measured on the given track
+    pub fn on_track(&self, track: usize) -> FitnessRecord {
+        let samples = self
+            .samples
+            .iter()
+            .zip(&self.tracks)
+            .filter(|(_, t)| **t == track)
+            .map(|(f, _)| *f)
+            .collect::<Vec<_>>();
+        FitnessRecord {
+            tracks: vec![track; samples.len()],
+            samples,
+            last_generation: self.last_generation,
+            behaviors: vec![],
+        }
+    }
+
+    //When the track set changes, e.g. after the tracks evolved. `sources` has the old index of every new track,
+    //samples measured on tracks that are gone are dropped, samples of tracks that appear twice are copied.
+    pub fn remap_tracks(&mut self, sources: &[Option<usize>]) {
+        let (mut samples, mut tracks) = (vec![], vec![]);
+        for (fitness, old) in self.samples.iter().zip(&self.tracks) {
+            for (new, source) in sources.iter().enumerate() {
+                if *source == Some(*old) {
+                    samples.push(*fitness);
+                    tracks.push(new);
+                }
+            }
+        }
+        self.samples = samples;
+        self.tracks = tracks;
+    }
+
+    //Whether the vehicle was evaluated on every track of the set at least once
+    pub fn covers_tracks(&self, track_count: usize) -> bool {
+        (0..track_count).all(|track| self.tracks.contains(&track))
+    }
+
+    pub fn last_generation(&self) -> usize {
+        self.last_generation
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.samples.iter().sum::<i64>() as f64 / self.samples.len() as f64
+    }
+
+    pub fn min(&self) -> i64 {
+        *self.samples.iter().min().expect("empty fitness record")
+    }
+
+    pub fn max(&self) -> i64 {
+        *self.samples.iter().max().expect("empty fitness record")
+    }
+
+    pub fn variance(&self) -> f64 {
+        let mean = self.mean();
+        self.samples
+            .iter()
+            .map(|x| (*x as f64 - mean).powi(2))
+            .sum::<f64>()
+            / self.samples.len() as f64
+    }
+
+    pub fn statistic(&self, statistic: FitnessStatistic) -> i64 {
+        match statistic {
+            FitnessStatistic::Mean => self.mean().round() as i64,
+            FitnessStatistic::Min => self.min(),
+            FitnessStatistic::Max => self.max(),
+        }
+    }
+
+    //The fitness used for selection: the statistic on every track, aggregated over the track set
+    pub fn score(&self, statistic: FitnessStatistic, track_set: &TrackSet) -> i64 {
+        let mut tracks = self.tracks.clone();
+        tracks.sort_unstable();
+        tracks.dedup();
+
+        if tracks.len() <= 1 {
+            return self.statistic(statistic);
+        }
+
+        let per_track = tracks
+            .into_iter()
+            .map(|track| (track, self.on_track(track).statistic(statistic)))
+            .collect::<Vec<_>>();
+        track_set.aggregate(&per_track)
+    }
+}
+
+//Takes the X coordinates (in pixels) of every block of a vehicle, returns its fitness and whether it fell apart
+pub fn fitness_from_block_positions(blocks: &[i64]) -> (i64, bool) {
+    let max_diff = 1000; //How far min/max can be apart in X coordinates before we start punishment
+
+    let mut fitness = mean_block_position(blocks);
+
+    let mut fitness_punishment_multiplier = 1.0;
+
+    let mut fell_apart = false;
+    let (min, max) = (blocks.iter().min(), blocks.iter().max());
+    if let (Some(min), Some(max)) = (min, max) {
+        let diff = (max - min).abs();
+        if diff > max_diff {
+            fitness_punishment_multiplier *= 0.1;
+            fell_apart = true;
+        }
+    }
+
+    fitness *= fitness_punishment_multiplier;
+
+    (fitness.round() as i64, fell_apart)
+}
+
+//Average X coordinate of the blocks, 0 if there are none
+pub fn mean_block_position(blocks: &[i64]) -> f64 {
+    if blocks.is_empty() {
+        0.0
+    } else {
+        blocks.iter().sum::<i64>() as f64 / blocks.len() as f64
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    let mut record = FitnessRecord::default();
+    record.push(0, 100, 0);
+    record.push(0, 300, 0);
+    record.push(1, 200, 1);
+    assert_eq!(record.statistic(FitnessStatistic::Mean), 200);
+    assert_eq!(record.statistic(FitnessStatistic::Min), 100);
+    assert_eq!(record.statistic(FitnessStatistic::Max), 300);
+    assert_eq!(record.on_track(0).len(), 2);
+    assert!(record.covers_tracks(2));
+    assert!(!record.covers_tracks(3));
+
+    //The worst track decides, the minimum on track 0 is worse than the one on track 1
+    let track_set = TrackSet {
+        enabled: true,
+        aggregation: crate::track::TrackAggregation::Min,
+        ..Default::default()
+    };
+    assert_eq!(record.score(FitnessStatistic::Min, &track_set), 100);
+
+    let mut remapped = record.clone();
+    remapped.remap_tracks(&[None, Some(1), Some(1)]);
+    assert_eq!(remapped.len(), 2);
+    assert!(remapped.on_track(0).is_empty());
+    assert_eq!(remapped.on_track(2).mean(), 200.);
+
+    let expiry = 5;
+    for generation in 1..10 {
+        assert!(CachePolicy::AlwaysReuse.reuses(&record, generation, expiry));
+        assert!(!CachePolicy::ReevaluateAndAverage.reuses(&record, generation, expiry));
+        assert!(!CachePolicy::AlwaysReuse.expired(&record, generation, expiry));
+        assert!(!CachePolicy::ReevaluateAndAverage.expired(&record, generation, expiry));
+
+        let expired = generation >= 6; //Last sample was added in generation 1
+        let policy = CachePolicy::ExpireAfterGenerations;
+        assert_eq!(policy.reuses(&record, generation, expiry), !expired);
+        assert_eq!(policy.expired(&record, generation, expiry), expired);
+    }
+}
@@ -0,0 +1,237 @@
+use crate::genome::Genome;
+use std::collections::HashMap;
+
+use log::{debug, error, info, trace, warn}; //IMPORTANT or you won't get any output during tests!
+
+//A gene that was changed by mutation
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Mutation {
+    pub gene: usize, //Index in Genome::genes()
+    pub from: u32,
+    pub to: u32,
+}
+
+//How a child came about
+#[derive(Clone, Debug)]
+pub struct Birth<G: Genome> {
+    pub child: G,
+    pub parents: [G; 2], //The part before the crossover point comes from the first one
+    pub crossover_point: Option<usize>,
+    pub mutations: Vec<Mutation>, //Only the ones that actually changed a gene
+}
+
+//Crossover of two parents and mutation of both children, the way all of evolution breeds, but keeping track of
+//what happened to every child
+pub fn breed<G: Genome>(father: &G, mother: &G, mutation_amount: usize) -> (Birth<G>, Birth<G>) {
+    let (brother, sister, crossover_point) = father.crossover_at(mother);
+    let birth = |mut child: G, parents: [&G; 2]| {
+        let before = child.genes();
+        child.mutate(mutation_amount);
+        let mutations = before
+            .iter()
+            .zip(child.genes())
+            .enumerate()
+            .filter(|(_, (from, to))| **from != *to)
+            .map(|(gene, (from, to))| Mutation {
+                gene,
+                from: *from,
+                to,
+            })
+            .collect();
+
+        Birth {
+            child,
+            parents: [parents[0].clone(), parents[1].clone()],
+            crossover_point,
+            mutations,
+        }
+    };
+
+    (
+        birth(brother, [father, mother]),
+        birth(sister, [mother, father]),
+    )
+}
+
+#[derive(Clone, Debug)]
+pub struct Individual<G: Genome> {
+    pub id: usize,
+    pub genome: G,
+    pub parents: Option<[usize; 2]>, //None for random genomes and genomes from outside, e.g. a loaded archive
+    pub crossover_point: Option<usize>,
+    pub mutations: Vec<Mutation>,
+    pub generation: usize,    //The one it was born in
+    pub fitness: Option<i64>, //Latest known fitness
+}
+
+//Every individual that ever was in the population, with its parents. Every birth or registration gets its own ID,
+//even if its genes are identical to an earlier individual (a clone, a re-injected genome or a copy of the best one).
+pub struct Genealogy<G: Genome> {
+    individuals: Vec<Individual<G>>, //Indexed by ID
+    ids: HashMap<G, Vec<usize>>,     //Every individual with these genes, oldest first
+}
+
+impl<G: Genome> Default for Genealogy<G> {
+    fn default() -> Self {
+        Genealogy {
+            individuals: vec![],
+            ids: HashMap::new(),
+        }
+    }
+}
+
+impl<G: Genome> Genealogy<G> {
+    pub fn len(&self) -> usize {
+        self.individuals.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.individuals.is_empty()
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Individual<G>> {
+        self.individuals.get(id)
+    }
+
+    //Latest individual with these genes, which is the one most likely still in the population
+    pub fn id(&self, genome: &G) -> Option<usize> {
+        self.ids(genome).last().copied()
+    }
+
+    pub fn ids(&self, genome: &G) -> &[usize] {
+        self.ids.get(genome).map_or(&[], |ids| ids.as_slice())
+    }
+
+    //Highest latest known fitness
+    pub fn champion(&self) -> Option<&Individual<G>> {
+        self.individuals
+            .iter()
+            .filter(|i| i.fitness.is_some())
+            .max_by_key(|i| i.fitness)
+    }
+
+    //New individual without parents, e.g. a random genome or one from outside
+    pub fn register(&mut self, genome: &G, generation: usize) -> usize {
+        self.add(Individual {
+            id: self.individuals.len(),
+            genome: genome.clone(),
+            parents: None,
+            crossover_point: None,
+            mutations: vec![],
+            generation,
+            fitness: None,
+        })
+    }
+
+    //ID of the child, which is new even if its genes aren't. The parents are the latest individuals with their genes
+    //from an earlier generation (siblings with the same genes may have been born already), unknown parents get
+    //registered first.
+    pub fn record_birth(&mut self, birth: &Birth<G>, generation: usize) -> usize {
+        let mut parent = |genome: &G| {
+            let ids = self.ids(genome);
+            let earlier = ids
+                .iter()
+                .rev()
+                .find(|id| self.individuals[**id].generation < generation);
+            match earlier.or_else(|| ids.last()) {
+                Some(id) => *id,
+                None => self.register(genome, generation),
+            }
+        };
+        let parents = [parent(&birth.parents[0]), parent(&birth.parents[1])];
+        self.add(Individual {
+            id: self.individuals.len(),
+            genome: birth.child.clone(),
+            parents: Some(parents),
+            crossover_point: birth.crossover_point,
+            mutations: birth.mutations.clone(),
+            generation,
+            fitness: None,
+        })
+    }
+
+    //Fitness belongs to the genes, so every individual with them gets it
+    pub fn set_fitness(&mut self, genome: &G, fitness: i64) {
+        let ids = match self.ids.get(genome) {
+            Some(ids) => ids,
+            None => return trace!("genealogy: fitness of an unknown genome"),
+        };
+        for id in ids {
+            self.individuals[*id].fitness = Some(fitness);
+        }
+    }
+
+    fn add(&mut self, individual: Individual<G>) -> usize {
+        let id = individual.id;
+        self.ids
+            .entry(individual.genome.clone())
+            .or_default()
+            .push(id);
+        self.individuals.push(individual);
+        id
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use crate::vehicle::{Block, Vehicle};
+
+    let mut genealogy = Genealogy::default();
+    let wheels = Vehicle::new_fill_with(Block::Wheel);
+    let panels = Vehicle::new_fill_with(Block::Panel);
+    assert_eq!(genealogy.register(&wheels, 0), 0);
+    assert_eq!(genealogy.register(&wheels, 1), 1); //Same design, but another individual
+    assert_eq!(genealogy.ids(&wheels), &[0, 1]);
+    assert_eq!(genealogy.id(&wheels), Some(1));
+
+    let (brother, _) = breed(&wheels, &panels, 3);
+    assert!(brother.crossover_point.is_some());
+    assert!(brother.mutations.len() <= 3);
+    assert!(brother.mutations.iter().all(|m| m.from != m.to));
+
+    let id = genealogy.record_birth(&brother, 1);
+    let child = genealogy.get(id).unwrap();
+    assert_eq!(child.parents, Some([0, 2])); //Wheels from an earlier generation, panels got registered
+    assert_eq!(child.generation, 1);
+
+    //The same child born again is a new individual with its own parents
+    let (clone, _) = breed(&wheels, &wheels, 0);
+    let first = genealogy.record_birth(&clone, 2);
+    let second = genealogy.record_birth(&clone, 3);
+    assert_ne!(first, second);
+    assert_eq!(genealogy.get(second).unwrap().generation, 3);
+    assert_eq!(genealogy.ids(&wheels), &[0, 1, first, second]);
+
+    genealogy.set_fitness(&brother.child, 100);
+    assert_eq!(genealogy.champion().unwrap().id, id);
+    genealogy.set_fitness(&wheels, 50);
+    assert!(genealogy
+        .ids(&wheels)
+        .iter()
+        .all(|id| genealogy.get(*id).unwrap().fitness == Some(50)));
+
+    //The simulator records every child it breeds, with parents from the generation before
+    use crate::{
+        fitness::GlobalFitnessMap,
+        genetics_simulator::{EvolutionParams, GeneticsSimulator},
+    };
+    let map: GlobalFitnessMap = std::sync::Arc::new(dashmap::DashMap::new());
+    map.entry(wheels.clone()).or_default().push(0, 100, 0);
+    map.entry(panels.clone()).or_default().push(0, 50, 0);
+    let params = EvolutionParams {
+        tournament_k: 2,
+        ..EvolutionParams::default()
+    };
+    let mut sim = GeneticsSimulator::from_population(vec![wheels, panels]);
+    sim.fill_in_fitness(&map, &params);
+    sim.step(&params);
+    let genealogy = sim.genealogy();
+    assert_eq!(genealogy.len(), 4);
+    assert_eq!(genealogy.champion().unwrap().id, 0);
+    for (child, _) in sim.get_population() {
+        let child = genealogy.get(genealogy.id(child).unwrap()).unwrap();
+        assert_eq!(child.generation, 1);
+        assert!(child.parents.unwrap().iter().all(|parent| *parent < 2));
+    }
+}
@@ -1,38 +1,151 @@
 use crate::{
-    plugins::genetics::{GlobalFitnessMap, SimulationParams},
+    diversity::Diversity,
+    fitness::{CachePolicy, FitnessRecord, FitnessStatistic, GlobalFitnessMap},
+    genealogy::{self, Genealogy},
+    genome::Genome,
+    islands::{self, IslandParams, IslandStatistics},
+    niching::{self, Niching},
+    novelty::{Descriptor, NoveltyArchive, NoveltyParams, Objective},
+    nsga2::{self, Objectives},
+    speciation::{Speciation, SpeciationParams},
+    steady_state::{self, SteadyStateParams},
+    track::TrackSet,
     vehicle::Vehicle,
 };
 
 use rand::seq::IteratorRandom;
-use rand::Rng;
+use std::collections::HashMap;
 
 use log::{debug, error, info, trace, warn}; //IMPORTANT or you won't get any output during tests!
 
-#[derive(new, Debug)]
+//The settings the simulator needs, the bevy app fills them in from its SimulationParams
+#[derive(Clone, Debug)]
+pub struct EvolutionParams {
+    pub tournament_k: usize,
+    pub mutation_amount: usize,
+    pub selection_statistic: FitnessStatistic,
+    pub cache_policy: CachePolicy,
+    pub cache_expiry_generations: usize, //Only used by CachePolicy::ExpireAfterGenerations
+    pub track_count: usize,              //Number of tracks every genome gets evaluated on
+    pub track_set: TrackSet,             //How the fitness on every track is aggregated
+    pub niching: Niching,
+    pub sharing_radius: f64, //In genes, only used by Niching::FitnessSharing
+    pub speciation: SpeciationParams, //Only used by Niching::Speciation
+    pub islands: IslandParams,
+    pub novelty: NoveltyParams,
+    pub multi_objective: bool, //NSGA-II on the objectives given to fill_in_objectives, instead of the fitness
+    pub steady_state: SteadyStateParams, //Only used by steady_state_step
+}
+
+impl Default for EvolutionParams {
+    fn default() -> Self {
+        EvolutionParams {
+            tournament_k: 10,
+            mutation_amount: 1,
+            selection_statistic: FitnessStatistic::Mean,
+            cache_policy: CachePolicy::ReevaluateAndAverage,
+            cache_expiry_generations: 5,
+            track_count: 1,
+            track_set: TrackSet::default(),
+            niching: Niching::Off,
+            sharing_radius: 6.,
+            speciation: SpeciationParams::default(),
+            islands: IslandParams::default(),
+            novelty: NoveltyParams::default(),
+            multi_objective: false,
+            steady_state: SteadyStateParams::default(),
+        }
+    }
+}
+
+impl EvolutionParams {
+    pub fn score(&self, record: &FitnessRecord) -> i64 {
+        record.score(self.selection_statistic, &self.track_set)
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct GenerationalStatistics {
     pub avg_fitness: f64,
     pub max_fitness: f64,
+    pub min_fitness: f64,
+    pub median_fitness: f64,
+    pub stddev_fitness: f64,
+    pub diversity: Diversity,
+    pub curriculum_stage: usize,
+    pub curriculum_goal: Option<i64>, //None if curriculum mode was off
+    pub reached_goal: f64,            //Fraction of the population that reached the curriculum goal
+    pub species: usize,               //0 unless Niching::Speciation is on
+    pub avg_novelty: Option<f64>,     //None unless novelty search is on
+    pub evaluations: usize,           //Genomes evaluated (or looked up) so far, in total
+}
+#[derive(new, Debug)]
+pub struct CacheStatistics {
+    pub hits: usize,
+    pub lookups: usize,
 }
-pub struct GeneticsSimulator {
-    population: Pop,
+
+//Evolves any kind of Genome, the bevy app uses it for vehicles
+pub struct GeneticsSimulator<G: Genome = Vehicle> {
+    population: Pop<G>,
     generational_statistics: Vec<GenerationalStatistics>,
+    cache_statistics: Vec<CacheStatistics>,
+    curriculum_stage: usize,
+    curriculum_goal: Option<i64>, //Fitness is capped at this, set by apply_curriculum
+    crowding_parents: Option<Pop<G>>, //Parent of every genome in the population, with deterministic crowding
+    speciation: Speciation<G>,
+    island_statistics: Vec<Vec<IslandStatistics>>, //Every island in every generation, empty while the island model is off
+    descriptors: HashMap<G, Descriptor>, //Behavior of the genomes in the population, filled in with the fitness
+    novelty_archive: NoveltyArchive,
+    objectives: HashMap<G, Objectives>, //Filled in by fill_in_objectives, only used in multi-objective mode
+    nsga_parents: Option<Vec<(G, Objectives)>>, //Survivors of the last generation, they compete with their children
+    pareto_fronts: Vec<Vec<Objectives>>, //Of the last generation, the first one is the Pareto front
+    evaluations: usize,
+    steady_state_offspring: Vec<G>, //Bred by steady_state_step and not evaluated yet
+    unreported_evaluations: usize,  //Since the last statistics entry of the steady-state GA
+    breeding_debt: usize,           //Evaluations the steady-state GA hasn't bred offspring for yet
+    injections: Vec<G>,             //Queued by inject, they join the population in the next step
+    genealogy: Genealogy<G>,
 }
-pub type Pop = Vec<(Vehicle, Option<i64>)>; //Fitness
+pub type Pop<G = Vehicle> = Vec<(G, Option<i64>)>; //Fitness
 
-impl GeneticsSimulator {
+impl<G: Genome> GeneticsSimulator<G> {
     pub fn new(population_size: usize) -> Self {
+        Self::from_population((0..population_size).map(|_| G::random()).collect())
+    }
+
+    //Starts out with the given genomes instead of random ones
+    pub fn from_population(genomes: Vec<G>) -> Self {
         assert!(
-            population_size % 2 == 0,
+            genomes.len() % 2 == 0,
             "population size wasn't even ({})",
-            population_size
+            genomes.len()
         );
-        let mut population = vec![];
-        for _ in 0..population_size {
-            population.push((Vehicle::new(), None));
+        let mut genealogy = Genealogy::default();
+        for genome in &genomes {
+            genealogy.register(genome, 0);
         }
+        let population = genomes.into_iter().map(|genome| (genome, None)).collect();
         GeneticsSimulator {
             population,
             generational_statistics: vec![],
+            cache_statistics: vec![],
+            curriculum_stage: 0,
+            curriculum_goal: None,
+            crowding_parents: None,
+            speciation: Speciation::default(),
+            island_statistics: vec![],
+            descriptors: HashMap::new(),
+            novelty_archive: NoveltyArchive::default(),
+            objectives: HashMap::new(),
+            nsga_parents: None,
+            pareto_fronts: vec![],
+            evaluations: 0,
+            steady_state_offspring: vec![],
+            unreported_evaluations: 0,
+            breeding_debt: 0,
+            injections: vec![],
+            genealogy,
         }
     }
 
@@ -40,31 +153,179 @@ impl GeneticsSimulator {
         &self.generational_statistics
     }
 
-    pub fn get_population(&self) -> &Pop {
+    pub fn get_cache_statistics(&self) -> &Vec<CacheStatistics> {
+        &self.cache_statistics
+    }
+
+    //Number of generations that have been evaluated so far
+    pub fn generation(&self) -> usize {
+        self.generational_statistics.len()
+    }
+
+    //Generations since the max fitness last beat its all-time best, 0 if it just did
+    pub fn stagnant_generations(&self) -> usize {
+        let mut best = f64::NEG_INFINITY;
+        let mut last_improvement = 0;
+        for (i, stats) in self.generational_statistics.iter().enumerate() {
+            if stats.max_fitness > best {
+                best = stats.max_fitness;
+                last_improvement = i;
+            }
+        }
+        self.generation().saturating_sub(last_improvement + 1)
+    }
+
+    pub fn get_island_statistics(&self) -> &Vec<Vec<IslandStatistics>> {
+        &self.island_statistics
+    }
+
+    pub fn novelty_archive(&self) -> &NoveltyArchive {
+        &self.novelty_archive
+    }
+
+    //Objectives of every non-dominated front the last multi-objective generation was sorted into
+    pub fn get_pareto_fronts(&self) -> &Vec<Vec<Objectives>> {
+        &self.pareto_fronts
+    }
+
+    pub fn genealogy(&self) -> &Genealogy<G> {
+        &self.genealogy
+    }
+
+    pub fn speciation(&self) -> &Speciation<G> {
+        &self.speciation
+    }
+
+    pub fn curriculum_stage(&self) -> usize {
+        self.curriculum_stage
+    }
+
+    pub fn advance_curriculum(&mut self) {
+        self.curriculum_stage += 1;
+        info!("advanced to curriculum stage {}", self.curriculum_stage + 1);
+    }
+
+    //Only the part of the track up to the goal counts, so every fitness is capped at it.
+    //None turns curriculum mode off. Call this after fill_in_fitness.
+    pub fn apply_curriculum(&mut self, goal: Option<i64>) {
+        self.curriculum_goal = goal;
+
+        if let Some(goal) = goal {
+            for (_, fitness) in &mut self.population {
+                *fitness = fitness.map(|f| f.min(goal));
+            }
+        }
+    }
+
+    //Fraction of the population whose fitness reached the curriculum goal
+    pub fn reached_goal(&self) -> f64 {
+        self.share_reaching_goal(&self.population)
+    }
+
+    fn share_reaching_goal(&self, population: &Pop<G>) -> f64 {
+        let goal = match self.curriculum_goal {
+            Some(goal) => goal,
+            None => return 0.,
+        };
+        let reached = population
+            .iter()
+            .filter(|(_, fitness)| fitness.map_or(false, |f| f >= goal))
+            .count();
+        reached as f64 / population.len() as f64
+    }
+
+    pub fn get_population(&self) -> &Pop<G> {
         &self.population
     }
 
-    pub fn get_population_vehicles(&self) -> Vec<Vehicle> {
-        self.population.iter().map(|(v, _)| v.clone()).collect()
+    pub fn get_population_genomes(&self) -> Vec<G> {
+        self.population.iter().map(|(g, _)| g.clone()).collect()
     }
 
-    pub fn fill_in_fitness(&mut self, map: &GlobalFitnessMap) {
+    pub fn fill_in_fitness(&mut self, map: &GlobalFitnessMap<G>, params: &EvolutionParams) {
         info!(
             "filling in fitness in the simulator: we have {} entries to pick from",
             map.len()
         );
-        for (vehicle, fitness) in &mut self.population {
+        self.descriptors.clear();
+        for (genome, fitness) in &mut self.population {
             let old_fitness = *fitness;
-            *fitness = map.get(vehicle).map(|x| *x);
+            *fitness = map.get(genome).map(|x| params.score(&x));
+            if let Some(fitness) = fitness {
+                self.genealogy.set_fitness(genome, *fitness);
+            }
+            if let Some(descriptor) = map.get(genome).and_then(|x| x.descriptor()) {
+                self.descriptors.insert(genome.clone(), descriptor);
+            }
             trace!(
                 "fitness went from {:?} to {:?} (found it? {})",
                 old_fitness,
                 fitness,
-                map.contains_key(vehicle)
+                map.contains_key(genome)
             );
         }
     }
 
+    //Objectives for multi-objective mode, call this after fill_in_fitness.
+    //Genomes without objectives (e.g. nothing was recorded for them) don't take part in the selection.
+    pub fn fill_in_objectives(
+        &mut self,
+        map: &GlobalFitnessMap<G>,
+        objectives: impl Fn(&G, &FitnessRecord) -> Option<Objectives>,
+    ) {
+        self.objectives.clear();
+        for (genome, _) in &self.population {
+            if let Some(o) = map
+                .get(genome)
+                .and_then(|record| objectives(genome, &record))
+            {
+                self.objectives.insert(genome.clone(), o);
+            }
+        }
+    }
+
+    //Returns the population, with the fitness filled in for every genome that doesn't need to be evaluated again
+    pub fn lookup_cached_fitness(
+        &mut self,
+        map: &GlobalFitnessMap<G>,
+        params: &EvolutionParams,
+    ) -> Pop<G> {
+        let result = self
+            .population
+            .iter()
+            .map(|(genome, _)| (genome.clone(), self.cached_fitness(genome, map, params)))
+            .collect::<Vec<_>>();
+
+        let hits = result.iter().filter(|(_, f)| f.is_some()).count();
+        info!("fitness cache: {} hits out of {}", hits, result.len());
+        self.cache_statistics
+            .push(CacheStatistics::new(hits, result.len()));
+
+        result
+    }
+
+    //Fitness of a single genome if it doesn't need to be evaluated again, according to the cache policy
+    pub fn cached_fitness(
+        &self,
+        genome: &G,
+        map: &GlobalFitnessMap<G>,
+        params: &EvolutionParams,
+    ) -> Option<i64> {
+        let generation = self.generation();
+        let expiry = params.cache_expiry_generations;
+
+        //The genome gets evaluated again, and its new fitness mustn't be averaged with the stale one
+        map.remove_if(genome, |_, record| {
+            params.cache_policy.expired(record, generation, expiry)
+        });
+
+        let cached = map.get(genome).filter(|record| {
+            record.covers_tracks(params.track_count) //Tracks that were added later still need an evaluation
+                && params.cache_policy.reuses(record, generation, expiry)
+        });
+        cached.map(|record| params.score(&record))
+    }
+
     pub fn avg_fitness(&self) -> f64 {
         self.get_population()
             .iter()
@@ -81,7 +342,58 @@ impl GeneticsSimulator {
             .expect("empty population")
     }
 
-    pub fn step(&mut self, params: &SimulationParams) {
+    //Fitness and diversity of the current population, every genome needs a fitness
+    pub fn measure_generation(&self) -> GenerationalStatistics {
+        self.measure(&self.population)
+    }
+
+    fn measure(&self, population: &Pop<G>) -> GenerationalStatistics {
+        let mut fitness = population
+            .iter()
+            .map(|(_, f)| f.expect("can't calculate statistics: missing fitness") as f64)
+            .collect::<Vec<_>>();
+        fitness.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        let n = fitness.len();
+        let avg_fitness = fitness.iter().sum::<f64>() / n as f64;
+        let median_fitness = if n % 2 == 0 {
+            (fitness[n / 2 - 1] + fitness[n / 2]) / 2.
+        } else {
+            fitness[n / 2]
+        };
+        let variance = fitness
+            .iter()
+            .map(|f| (f - avg_fitness).powi(2))
+            .sum::<f64>()
+            / n as f64;
+
+        GenerationalStatistics {
+            avg_fitness,
+            max_fitness: fitness[n - 1],
+            min_fitness: fitness[0],
+            median_fitness,
+            stddev_fitness: variance.sqrt(),
+            diversity: Diversity::measure(
+                &population
+                    .iter()
+                    .map(|(g, _)| g.clone())
+                    .collect::<Vec<_>>(),
+            ),
+            curriculum_stage: self.curriculum_stage,
+            curriculum_goal: self.curriculum_goal,
+            reached_goal: self.share_reaching_goal(population),
+            species: self.speciation.species().len(),
+            avg_novelty: None,
+            evaluations: self.evaluations,
+        }
+    }
+
+    pub fn step(&mut self, params: &EvolutionParams) {
+        self.breed_next_generation(params);
+        self.apply_injections();
+    }
+
+    fn breed_next_generation(&mut self, params: &EvolutionParams) {
         //1. evaluate fitness
         //2. variation (crossover and then mutation)
         //3. rank based selection (truncation or tournament) -> they become the new parents
@@ -90,31 +402,381 @@ impl GeneticsSimulator {
         //we're gonna do windows instead of chunks for crossover
         //and we're gonna make it so parents don't survive to the next generation
 
-        let tournament_k = params.tournament_k as usize;
+        let tournament_k = params.tournament_k;
 
-        let avg_fitness = self.avg_fitness();
-        let max_fitness = self.max_fitness() as f64;
+        //With deterministic crowding the evaluated children compete with their parents first
+        match self.crowding_parents.take() {
+            Some(parents) if params.niching == Niching::DeterministicCrowding => {
+                self.population = niching::crowding_survivors(&self.population, &parents);
+            }
+            _ => {}
+        }
 
-        let stats = GenerationalStatistics::new(avg_fitness, max_fitness);
+        if params.niching == Niching::Speciation {
+            let generation = self.generation();
+            self.speciation
+                .speciate(&self.population, &params.speciation, generation);
+        }
+
+        self.evaluations += self.population.len();
+        let mut stats = self.measure_generation();
+        let islands = params.islands.ranges(self.population.len());
+        if let Some(ranges) = &islands {
+            self.island_statistics
+                .push(islands::island_statistics(&self.population, ranges));
+        }
+
+        //Like fitness sharing, novelty only changes who gets selected, the statistics use the real fitness.
+        //Deterministic crowding always compares the real fitness of children and parents.
+        if params.novelty.objective != Objective::Fitness {
+            let novelty = self.novelty_scores(&params.novelty);
+            stats.avg_novelty = Some(novelty.iter().sum::<f64>() / novelty.len() as f64);
+            if params.niching != Niching::DeterministicCrowding {
+                for ((_, fitness), novelty) in self.population.iter_mut().zip(&novelty) {
+                    *fitness = fitness.map(|f| params.novelty.objective(f, *novelty));
+                }
+            }
+        }
+
+        info!(
+            "generation {}: {} unique genomes, mean distance {:.1}, entropy {:.2}",
+            self.generation() + 1,
+            stats.diversity.unique_genomes,
+            stats.diversity.mean_distance,
+            stats.diversity.entropy
+        );
         self.generational_statistics.push(stats);
 
-        let new_parents = self.tournament_selection(tournament_k, self.population.len());
-        let mut children = self.crossover(&new_parents);
+        //NSGA-II replaces the whole selection, the fitness is only used for the statistics
+        if params.multi_objective {
+            self.nsga2_step(params);
+            return;
+        }
 
-        assert_eq!(children.len(), (new_parents).len());
+        //Islands do their own selection, niching only works on the whole population
+        if let Some(ranges) = islands {
+            self.island_step(params, &ranges);
+            return;
+        }
 
-        for (child, _) in &mut children {
-            child.mutate(params.mutation_amount as usize);
+        if params.niching == Niching::DeterministicCrowding {
+            let generation = self.generation();
+            let (children, parents) = niching::crowding_children(
+                &self.population,
+                params.mutation_amount,
+                &mut self.genealogy,
+                generation,
+            );
+            self.population = children;
+            self.crowding_parents = Some(parents);
+            return;
         }
 
+        if params.niching == Niching::Speciation {
+            let generation = self.generation();
+            self.population = self.speciation.offspring(
+                &self.population,
+                &params.speciation,
+                tournament_k,
+                params.mutation_amount,
+                generation,
+                &mut self.genealogy,
+            );
+            return;
+        }
+
+        //Fitness sharing only changes who gets selected, the statistics use the real fitness
+        let selection_pool = if params.niching == Niching::FitnessSharing {
+            niching::shared_fitness(&self.population, params.sharing_radius)
+        } else {
+            self.population.clone()
+        };
+
+        let new_parents =
+            Self::tournament_selection(&selection_pool, tournament_k, self.population.len());
+        let children = self.breed(&new_parents, params.mutation_amount);
+
+        assert_eq!(children.len(), (new_parents).len());
+
         self.population = children;
     }
 
-    pub fn overwrite_population(&mut self, pop: Vec<Vehicle>) {
-        self.population = pop.into_iter().map(|vehicle| (vehicle, None)).collect();
+    //Steady-state GA, called with the genomes that just finished all their evaluations. Evaluated offspring replace
+    //the worst genome of the population, and new offspring are bred for every `offspring` evaluations once the
+    //initial population is done. Returns the offspring that need to be evaluated next.
+    //Every `report_interval` evaluations the evaluated part of the population is measured, instead of every generation.
+    pub fn steady_state_step(
+        &mut self,
+        evaluated: &[G],
+        map: &GlobalFitnessMap<G>,
+        params: &EvolutionParams,
+    ) -> Vec<G> {
+        //Injected genomes are evaluated like offspring, and then replace the worst genome like them
+        let generation = self.generation();
+        let injected = std::mem::take(&mut self.injections);
+        for genome in &injected {
+            self.genealogy.register(genome, generation);
+        }
+        self.steady_state_offspring.extend(injected.iter().cloned());
+
+        let goal = self.curriculum_goal;
+        let mut finished = 0;
+        for genome in evaluated {
+            let fitness = match map.get(genome) {
+                Some(record) => params.score(&record),
+                None => {
+                    warn!("steady-state: an evaluated genome has no fitness, ignoring it");
+                    continue;
+                }
+            };
+            self.genealogy.set_fitness(genome, fitness);
+            let fitness = Some(goal.map_or(fitness, |goal| fitness.min(goal)));
+
+            //Identical genomes are evaluated together, so all copies are done.
+            //Genomes that aren't part of the evolution (e.g. requested in the GUI) don't match anything.
+            for entry in &mut self.population {
+                if entry.1.is_none() && entry.0 == *genome {
+                    entry.1 = fitness;
+                    finished += 1;
+                }
+            }
+            while let Some(i) = self.steady_state_offspring.iter().position(|g| g == genome) {
+                let child = self.steady_state_offspring.swap_remove(i);
+                let worst = steady_state::worst_evaluated(&self.population)
+                    .expect("steady-state: offspring was bred before anything was evaluated");
+                self.population[worst] = (child, fitness);
+                finished += 1;
+            }
+        }
+
+        self.evaluations += finished;
+        self.unreported_evaluations += finished;
+        self.breeding_debt += finished;
+
+        let interval = params.steady_state.report_interval.max(1) as usize;
+        if self.unreported_evaluations >= interval {
+            self.unreported_evaluations -= interval;
+            let evaluated = self
+                .population
+                .iter()
+                .filter(|(_, f)| f.is_some())
+                .cloned()
+                .collect::<Vec<_>>();
+            let stats = self.measure(&evaluated);
+            info!(
+                "{} evaluations: avg fitness {:.0}, max fitness {:.0}",
+                self.evaluations, stats.avg_fitness, stats.max_fitness
+            );
+            self.generational_statistics.push(stats);
+        }
+
+        if self.population.iter().any(|(_, f)| f.is_none()) {
+            return injected; //Still evaluating the initial population
+        }
+
+        let offspring = params.steady_state.offspring.clamp(1, 2) as usize;
+        let matings = self.breeding_debt / offspring;
+        self.breeding_debt %= offspring;
+
+        let mut rng = rand::thread_rng();
+        let k = params.tournament_k.clamp(1, self.population.len());
+        let mut tournament = || {
+            self.population
+                .iter()
+                .choose_multiple(&mut rng, k)
+                .into_iter()
+                .max_by_key(|(_, f)| f.unwrap())
+                .unwrap()
+                .0
+                .clone()
+        };
+
+        let mut births = vec![];
+        for _ in 0..matings {
+            let (father, mother) = (tournament(), tournament());
+            let (brother, sister) = genealogy::breed(&father, &mother, params.mutation_amount);
+            births.extend(vec![brother, sister].into_iter().take(offspring));
+        }
+
+        let generation = self.generation();
+        let mut children = vec![];
+        for birth in births {
+            self.genealogy.record_birth(&birth, generation);
+            children.push(birth.child);
+        }
+
+        self.steady_state_offspring.extend(children.iter().cloned());
+        injected.into_iter().chain(children).collect()
+    }
+
+    //Every island is evolved on its own with its own settings, and stays in the same part of the population
+    fn island_step(&mut self, params: &EvolutionParams, ranges: &[std::ops::Range<usize>]) {
+        let interval = params.islands.migration_interval.max(1) as usize;
+        if self.generation() % interval == 0 {
+            islands::migrate(&mut self.population, ranges, &params.islands);
+        }
+
+        let mut next = vec![];
+        for (range, settings) in ranges.iter().zip(&params.islands.islands) {
+            let island = self.population[range.clone()].to_vec();
+            let pool = if params.niching == Niching::FitnessSharing {
+                niching::shared_fitness(&island, params.sharing_radius)
+            } else {
+                island
+            };
+
+            let k = (settings.tournament_k as usize).clamp(2, range.len());
+            let new_parents = Self::tournament_selection(&pool, k, range.len());
+            next.extend(self.breed(&new_parents, settings.mutation_amount as usize));
+        }
+
+        self.population = next;
+    }
+
+    //NSGA-II: the children compete with the survivors of the last generation. The best fronts survive, and parents
+    //are picked from them by binary tournaments on front and crowding distance.
+    fn nsga2_step(&mut self, params: &EvolutionParams) {
+        let n = self.population.len();
+        let mut pool = self
+            .population
+            .iter()
+            .filter_map(|(genome, _)| {
+                let objectives = self.objectives.get(genome)?;
+                Some((genome.clone(), objectives.clone()))
+            })
+            .collect::<Vec<_>>();
+        if pool.len() < n {
+            warn!(
+                "multi-objective: {} genomes have no objectives, they can't be selected",
+                n - pool.len()
+            );
+        }
+        pool.extend(self.nsga_parents.take().unwrap_or_default());
+        if pool.is_empty() {
+            error!("multi-objective: no genome has objectives, keeping the population");
+            return;
+        }
+
+        let objectives = pool.iter().map(|(_, o)| o.clone()).collect::<Vec<_>>();
+        self.pareto_fronts = nsga2::non_dominated_sort(&objectives)
+            .iter()
+            .map(|front| front.iter().map(|i| objectives[*i].clone()).collect())
+            .collect();
+
+        let survivors = nsga2::select_survivors(&objectives, n)
+            .into_iter()
+            .map(|i| pool[i].clone())
+            .collect::<Vec<_>>();
+        let ranks = nsga2::rank(&survivors.iter().map(|(_, o)| o.clone()).collect::<Vec<_>>());
+        info!(
+            "multi-objective: {} genomes on the Pareto front, {} fronts",
+            self.pareto_fronts[0].len(),
+            self.pareto_fronts.len()
+        );
+
+        let parents = nsga2::crowded_tournament(&ranks, n)
+            .into_iter()
+            .map(|i| (survivors[i].0.clone(), None))
+            .collect::<Pop<G>>();
+        self.population = self.breed(&parents, params.mutation_amount);
+        self.nsga_parents = Some(survivors);
+    }
+
+    //Novelty of every genome in the population, the most novel behaviors are added to the archive
+    fn novelty_scores(&mut self, params: &NoveltyParams) -> Vec<f64> {
+        let descriptors = self
+            .population
+            .iter()
+            .map(|(genome, _)| self.descriptors.get(genome).copied())
+            .collect::<Vec<_>>();
+        if descriptors.iter().any(|d| d.is_none()) {
+            warn!(
+                "novelty search: some genomes have no recorded behavior, they count as not novel"
+            );
+        }
+
+        let novelty = self
+            .novelty_archive
+            .novelty(&descriptors, params.nearest_neighbors as usize);
+        self.novelty_archive.add(
+            &descriptors,
+            &novelty,
+            params.archive_per_generation as usize,
+        );
+        novelty
+    }
+
+    //Queues a genome from outside, e.g. from the hall of fame. It can't take a slot of the current population,
+    //which may already be running, so it joins the next one: in place of a child in the next generation,
+    //or with the steady-state GA, it's returned by the next steady_state_step to be evaluated like offspring.
+    pub fn inject(&mut self, genome: G) {
+        info!("queued a genome for injection");
+        self.injections.push(genome);
+    }
+
+    //Injected genomes take the place of the last children that weren't evaluated yet (e.g. not kept as elites)
+    fn apply_injections(&mut self) {
+        let generation = self.generation();
+        let mut slots = (0..self.population.len())
+            .rev()
+            .filter(|i| self.population[*i].1.is_none())
+            .collect::<Vec<_>>()
+            .into_iter();
+        for genome in std::mem::take(&mut self.injections) {
+            match slots.next() {
+                Some(i) => {
+                    info!("injecting a genome in place of genome {}", i);
+                    self.genealogy.register(&genome, generation);
+                    self.population[i] = (genome, None);
+                }
+                None => warn!("no room to inject a genome, dropping it"),
+            }
+        }
+    }
+
+    //Genomes that weren't bred by the simulator are added to the genealogy without parents
+    pub fn overwrite_population(&mut self, pop: Vec<G>) {
+        let generation = self.generation();
+        for genome in &pop {
+            self.genealogy.register(genome, generation);
+        }
+        self.replace_population(pop);
+    }
+
+    //Like step, but the next generation is bred outside of the simulator, e.g. from the MAP-Elites archive.
+    //Only the statistics of the evaluated generation are recorded. `breed` gets the generation its offspring
+    //is born in, and records the births in the genealogy itself.
+    pub fn step_from<F>(&mut self, params: &EvolutionParams, breed: F)
+    where
+        F: FnOnce(&mut Genealogy<G>, usize) -> Vec<G>,
+    {
+        self.evaluations += self.population.len();
+        let stats = self.measure_generation();
+        if let Some(ranges) = params.islands.ranges(self.population.len()) {
+            self.island_statistics
+                .push(islands::island_statistics(&self.population, &ranges));
+        }
+        self.generational_statistics.push(stats);
+
+        let generation = self.generation();
+        let offspring = breed(&mut self.genealogy, generation);
+        info!(
+            "generation {}: bred {} genomes outside of the simulator",
+            generation,
+            offspring.len()
+        );
+        self.replace_population(offspring);
+        self.apply_injections();
     }
 
-    pub fn tournament_selection(&self, k: usize, n: usize) -> Pop {
+    fn replace_population(&mut self, pop: Vec<G>) {
+        self.population = pop.into_iter().map(|genome| (genome, None)).collect();
+        self.crowding_parents = None;
+        self.nsga_parents = None;
+        self.steady_state_offspring.clear();
+    }
+
+    pub fn tournament_selection(pool: &Pop<G>, k: usize, n: usize) -> Pop<G> {
         //Select best individual from k randomly selected individuals.
         //Hold n tournaments to get n new individuals.
 
@@ -127,18 +789,18 @@ impl GeneticsSimulator {
 
         let mut result = vec![];
         for _ in 0..n {
-            let tournament = self.population.iter().choose_multiple(&mut rng, k);
+            let tournament = pool.iter().choose_multiple(&mut rng, k);
             let winner = tournament
                 .into_iter()
-                .max_by_key(|(_, x)| x.expect("you didn't calculate fitness for every vehicle yet"))
+                .max_by_key(|(_, x)| x.expect("you didn't calculate fitness for every genome yet"))
                 .unwrap();
 
             result.push(winner.clone());
         }
 
         info!(
-            "doing tournament selection on {} vehicles with k={} n={}",
-            self.population.len(),
+            "doing tournament selection on {} genomes with k={} n={}",
+            pool.len(),
             k,
             n
         );
@@ -152,7 +814,8 @@ impl GeneticsSimulator {
         info!("{:?}", self.population);
     }
 
-    pub fn crossover(&mut self, parents: &Pop) -> Pop {
+    //Crossover of every two neighboring parents, then mutation. The children are recorded in the genealogy.
+    pub fn breed(&mut self, parents: &Pop<G>, mutation_amount: usize) -> Pop<G> {
         let mut children = vec![];
         let mut rng = rand::thread_rng();
 
@@ -164,6 +827,7 @@ impl GeneticsSimulator {
         );
 
         let pop_subset = parents.to_owned();
+        let generation = self.generation();
 
         for parents in pop_subset.windows(2) {
             let father = &parents[0];
@@ -174,9 +838,7 @@ impl GeneticsSimulator {
                 father.1, mother.1
             );
 
-            let crossover_point = rng.gen_range(1..crate::vehicle::VEHICLE_SHAPE.1);
-
-            let (brother, sister) = father.0.one_point_crossover(&mother.0, crossover_point);
+            let (brother, sister) = genealogy::breed(&father.0, &mother.0, mutation_amount);
 
             children.push(brother);
             children.push(sister);
@@ -184,7 +846,131 @@ impl GeneticsSimulator {
 
         children
             .into_iter()
-            .map(|x| (x, None))
             .choose_multiple(&mut rng, parents.len()) //Pick from all brothers and sisters randomly
+            .into_iter()
+            .map(|birth| {
+                self.genealogy.record_birth(&birth, generation);
+                (birth.child, None)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dashmap::DashMap;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use serde::{Deserialize, Serialize};
+    use std::{cell::RefCell, sync::Arc};
+
+    thread_local! {
+        //Every test runs on its own thread, so every test starts with the same genomes
+        static RNG: RefCell<StdRng> = RefCell::new(StdRng::seed_from_u64(0));
+    }
+
+    //Bitstring, the fitness is the number of ones
+    #[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+    struct OneMax(Vec<bool>);
+
+    impl OneMax {
+        fn ones(&self) -> i64 {
+            self.0.iter().filter(|bit| **bit).count() as i64
+        }
+    }
+
+    impl Genome for OneMax {
+        fn random() -> Self {
+            RNG.with(|rng| OneMax((0..32).map(|_| rng.borrow_mut().gen_bool(0.2)).collect()))
+        }
+
+        fn mutate(&mut self, amount: usize) {
+            for _ in 0..amount {
+                let i = RNG.with(|rng| rng.borrow_mut().gen_range(0..self.0.len()));
+                self.0[i] = !self.0[i];
+            }
+        }
+
+        fn crossover(&self, other: &Self) -> (Self, Self) {
+            let point = RNG.with(|rng| rng.borrow_mut().gen_range(1..self.0.len()));
+            let brother = self.0[..point].iter().chain(&other.0[point..]).copied();
+            let sister = other.0[..point].iter().chain(&self.0[point..]).copied();
+            (OneMax(brother.collect()), OneMax(sister.collect()))
+        }
+
+        fn genes(&self) -> Vec<u32> {
+            self.0.iter().map(|bit| *bit as u32).collect()
+        }
+    }
+
+    fn evolve_one_max(params: &EvolutionParams) -> GeneticsSimulator<OneMax> {
+        let map: GlobalFitnessMap<OneMax> = Arc::new(DashMap::new());
+        let mut sim = GeneticsSimulator::<OneMax>::new(24);
+
+        for generation in 0..60 {
+            for (genome, _) in sim.get_population() {
+                let mut record = map.entry(genome.clone()).or_default();
+                record.push(0, genome.ones(), generation);
+            }
+            sim.fill_in_fitness(&map, params);
+            sim.step(params);
+        }
+
+        sim
+    }
+
+    //Smoke test of a whole run. Selection isn't seeded, so only things that hold for any run are checked.
+    #[test]
+    fn one_max() {
+        let sim = evolve_one_max(&EvolutionParams::default());
+        let stats = sim.get_generational_statistics();
+        info!("OneMax: {:?} -> {:?}", stats.first(), stats.last());
+        assert_eq!(stats.len(), 60);
+        assert_eq!(sim.get_population().len(), 24);
+        assert!(stats.iter().all(|s| s.diversity.population_size == 24));
+
+        let first = &stats.first().unwrap().diversity;
+        assert_eq!(first.gene_count, 32);
+        assert!(first.entropy > 0. && first.mean_distance > 0.);
+
+        //Every ancestor of the champion was born before it
+        let genealogy = sim.genealogy();
+        let champion = genealogy.champion().unwrap();
+        let best = stats.iter().map(|s| s.max_fitness).fold(0., f64::max);
+        assert_eq!(champion.fitness.unwrap() as f64, best);
+        let mut ancestors = vec![champion.id];
+        let mut visited = std::collections::HashSet::new();
+        while let Some(id) = ancestors.pop() {
+            if !visited.insert(id) {
+                continue; //Inbreeding, this ancestor was checked already
+            }
+            let individual = genealogy.get(id).unwrap();
+            for parent in individual.parents.iter().flatten() {
+                assert!(genealogy.get(*parent).unwrap().generation <= individual.generation);
+                assert!(*parent < id);
+                ancestors.push(*parent);
+            }
+        }
+    }
+
+    #[test]
+    fn cache_expiry() {
+        let map: GlobalFitnessMap<OneMax> = Arc::new(DashMap::new());
+        let sim = GeneticsSimulator::<OneMax>::new(2);
+        let genome = OneMax::random();
+        map.entry(genome.clone()).or_default().push(0, 100, 0);
+
+        let mut params = EvolutionParams {
+            cache_policy: CachePolicy::ExpireAfterGenerations,
+            cache_expiry_generations: 1,
+            ..EvolutionParams::default()
+        };
+        assert_eq!(sim.cached_fitness(&genome, &map, &params), Some(100));
+
+        params.cache_expiry_generations = 0; //Stale right away
+        assert_eq!(sim.cached_fitness(&genome, &map, &params), None);
+        map.entry(genome.clone()).or_default().push(0, 200, 0);
+        params.cache_expiry_generations = 1;
+        assert_eq!(sim.cached_fitness(&genome, &map, &params), Some(200)); //Not averaged with the stale 100
     }
 }
@@ -0,0 +1,32 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::{fmt::Debug, hash::Hash};
+
+//Anything the GeneticsSimulator can evolve. Vehicles are the main one, but the same selection and statistics
+//work for track parameters, or for bitstrings in tests.
+//Genomes are compared and hashed to look up their fitness in the GlobalFitnessMap.
+pub trait Genome: Clone + Eq + Hash + Debug + Serialize + DeserializeOwned {
+    fn random() -> Self;
+
+    //Changes `amount` randomly picked genes (a mutation may not change anything)
+    fn mutate(&mut self, amount: usize);
+
+    //Two children that both have genes of both parents
+    fn crossover(&self, other: &Self) -> (Self, Self);
+
+    //Like crossover, but also returns where the parents were cut, for the genealogy.
+    //None if the crossover doesn't have a single crossover point.
+    fn crossover_at(&self, other: &Self) -> (Self, Self, Option<usize>) {
+        let (brother, sister) = self.crossover(other);
+        (brother, sister, None)
+    }
+
+    //Every gene as a number, e.g. the block type of every cell. Used to measure diversity.
+    fn genes(&self) -> Vec<u32>;
+
+    //Number of genes that differ (Hamming distance)
+    fn distance(&self, other: &Self) -> f64 {
+        let (a, b) = (self.genes(), other.genes());
+        let different = a.iter().zip(&b).filter(|(x, y)| x != y).count();
+        (different + a.len().max(b.len()) - a.len().min(b.len())) as f64
+    }
+}
@@ -0,0 +1,112 @@
+use crate::{genome::Genome, vehicle::Vehicle};
+
+use log::{debug, error, info, trace, warn}; //IMPORTANT or you won't get any output during tests!
+
+#[derive(Clone, Debug)]
+pub struct HallOfFameEntry<G: Genome = Vehicle> {
+    pub genome: G,
+    pub fitness: i64,
+    pub generation: usize, //When it first got in
+}
+
+//The best genomes of all generations, every genome at most once, best first
+pub struct HallOfFame<G: Genome = Vehicle> {
+    size: usize,
+    entries: Vec<HallOfFameEntry<G>>,
+}
+
+impl<G: Genome> HallOfFame<G> {
+    pub fn new(size: usize) -> Self {
+        HallOfFame {
+            size,
+            entries: vec![],
+        }
+    }
+
+    pub fn entries(&self) -> &[HallOfFameEntry<G>] {
+        &self.entries
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    //Shrinking forgets the worst entries
+    pub fn resize(&mut self, size: usize) {
+        self.size = size;
+        self.entries.truncate(size);
+    }
+
+    //Genomes that are already in it get their fitness updated, since it changes when they're evaluated again.
+    //Returns whether the genome is in the hall of fame afterwards.
+    pub fn insert(&mut self, genome: &G, fitness: i64, generation: usize) -> bool {
+        match self.entries.iter_mut().find(|e| e.genome == *genome) {
+            Some(entry) => entry.fitness = fitness,
+            None => self.entries.push(HallOfFameEntry {
+                genome: genome.clone(),
+                fitness,
+                generation,
+            }),
+        }
+
+        //Stable, so older entries stay ahead of newer ones with the same fitness
+        self.entries.sort_by_key(|e| std::cmp::Reverse(e.fitness));
+        self.entries.truncate(self.size);
+        self.entries.iter().any(|e| e.genome == *genome)
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use crate::vehicle::Block;
+
+    let mut hall = HallOfFame::new(2);
+    let wheels = Vehicle::new_fill_with(Block::Wheel);
+    let panels = Vehicle::new_fill_with(Block::Panel);
+    let air = Vehicle::new_fill_with(Block::Air);
+
+    assert!(hall.insert(&wheels, 100, 0));
+    assert!(hall.insert(&panels, 50, 1));
+    assert!(!hall.insert(&air, 10, 2)); //Full, and worse than everything in it
+    assert!(hall.insert(&panels, 150, 3)); //Same vehicle, evaluated again
+    assert_eq!(hall.entries().len(), 2);
+    assert_eq!(hall.entries()[0].fitness, 150);
+    assert_eq!(hall.entries()[0].generation, 1);
+
+    hall.resize(1);
+    assert_eq!(hall.entries()[0].genome, panels);
+
+    //Genomes from the hall of fame are injected into the next generation, the current one may already be running
+    use crate::{
+        fitness::GlobalFitnessMap,
+        genetics_simulator::{EvolutionParams, GeneticsSimulator},
+    };
+    let designs = vec![wheels.clone(), panels.clone(), air.clone(), wheels];
+    let map: GlobalFitnessMap = std::sync::Arc::new(dashmap::DashMap::new());
+    for (design, fitness) in designs.iter().zip(&[100, 50, 10]) {
+        map.entry(design.clone()).or_default().push(0, *fitness, 0);
+    }
+    let params = EvolutionParams {
+        tournament_k: 2,
+        ..EvolutionParams::default()
+    };
+    let mut sim = GeneticsSimulator::from_population(designs.clone());
+    sim.fill_in_fitness(&map, &params);
+
+    let stripes = Vehicle::from(
+        (0..48)
+            .map(|i| [Block::Wheel, Block::Panel][i % 2])
+            .collect(),
+    );
+    sim.inject(stripes.clone());
+    sim.inject(stripes.clone());
+    assert_eq!(sim.get_population_genomes(), designs);
+
+    //Stripes can't be bred from the designs, so every copy was injected
+    sim.step(&params);
+    let population = sim.get_population();
+    assert_eq!(population.len(), 4);
+    assert_eq!(population[2..], vec![(stripes.clone(), None); 2][..]);
+    assert!(population[..2].iter().all(|(genome, _)| *genome != stripes));
+}
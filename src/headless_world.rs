@@ -0,0 +1,374 @@
+use crate::{
+    fitness::{fitness_from_block_positions, mean_block_position},
+    novelty::{Behavior, BehaviorRecorder},
+    track::{
+        obstacles::{obstacle_parts, platform_offset, Obstacle, PartBody},
+        Triangle,
+    },
+    vehicle::{block_physics, block_position, Block, Vehicle},
+};
+use ndarray::Array2;
+use rapier2d::{
+    dynamics::{
+        BallJoint, CCDSolver, FixedJoint, IntegrationParameters, IslandManager, JointSet,
+        RigidBodyBuilder, RigidBodyHandle, RigidBodySet,
+    },
+    geometry::{
+        BroadPhase, ColliderBuilder, ColliderHandle, ColliderSet, ColliderShape, NarrowPhase,
+    },
+    math::{Isometry, Point, Real, Vector},
+    pipeline::PhysicsPipeline,
+};
+
+use log::{debug, error, info, trace, warn}; //IMPORTANT or you won't get any output during tests!
+
+//A standalone rapier world containing only the terrain and a single vehicle.
+//Mirrors what the bevy world does in vehicle_manager, but without bevy or any rendering, so it can run on any thread
+//and in other tools that only need fitness.
+pub struct HeadlessWorld {
+    pipeline: PhysicsPipeline,
+    gravity: Vector<Real>,
+    integration_parameters: IntegrationParameters,
+    islands: IslandManager,
+    broad_phase: BroadPhase,
+    narrow_phase: NarrowPhase,
+    bodies: RigidBodySet,
+    colliders: ColliderSet,
+    joints: JointSet,
+    ccd_solver: CCDSolver,
+    scale: f32,
+    platforms: Vec<MovingPlatform>,
+    steps: u32, //Physics steps since the world was created, moving platforms follow this
+}
+
+//Everything that was spawned for a vehicle
+pub struct VehicleHandles {
+    pub bodies: Vec<RigidBodyHandle>,
+    pub colliders: Vec<ColliderHandle>,
+    pub wheels: Vec<ColliderHandle>,
+}
+
+struct MovingPlatform {
+    handle: RigidBodyHandle,
+    start: Vector<Real>, //Physics units
+    travel: [f32; 2],
+    period: f32,
+}
+
+impl HeadlessWorld {
+    pub fn new(terrain: ColliderShape, scale: f32) -> Self {
+        let mut bodies = RigidBodySet::new();
+        let mut colliders = ColliderSet::new();
+
+        let terrain_body = bodies.insert(RigidBodyBuilder::new_static().build());
+        let terrain_collider = ColliderBuilder::new(terrain)
+            .friction(0.3)
+            .restitution(0.1)
+            .build();
+        colliders.insert_with_parent(terrain_collider, terrain_body, &mut bodies);
+
+        HeadlessWorld {
+            pipeline: PhysicsPipeline::new(),
+            gravity: Vector::new(0.0, -9.81), //Same as the default RapierConfiguration
+            integration_parameters: IntegrationParameters::default(),
+            islands: IslandManager::new(),
+            broad_phase: BroadPhase::new(),
+            narrow_phase: NarrowPhase::new(),
+            bodies,
+            colliders,
+            joints: JointSet::new(),
+            ccd_solver: CCDSolver::new(),
+            scale,
+            platforms: vec![],
+            steps: 0,
+        }
+    }
+
+    //Simulates the vehicle for the given amount of seconds, returns its fitness, whether it fell apart and its behavior
+    pub fn evaluate(
+        terrain: &ColliderShape,
+        scale: f32,
+        vehicle: &Vehicle,
+        spawn: [f32; 2], //Pixels, fitness is measured from here
+        obstacles: &[Obstacle],
+        duration: f32,
+    ) -> (i64, bool, Behavior) {
+        //Build a fresh world for every vehicle, so no state leaks from one evaluation into the next
+        let mut world = HeadlessWorld::new(terrain.clone(), scale);
+        world.spawn_obstacles(obstacles);
+        let handles = world.spawn_vehicle(vehicle, spawn);
+
+        let blocks = |world: &HeadlessWorld| {
+            world
+                .block_x_positions(&handles.bodies)
+                .into_iter()
+                .map(|x| x - spawn[0].round() as i64) //Don't reward a lucky spawn position
+                .collect::<Vec<_>>()
+        };
+
+        let mut behavior = BehaviorRecorder::default();
+        let steps = (duration / world.integration_parameters.dt).ceil() as usize;
+        for _ in 0..steps {
+            world.step();
+            behavior.observe(
+                mean_block_position(&blocks(&world)) as f32,
+                world.max_height(&handles.bodies) - spawn[1],
+                world.wheels_touching(&handles),
+                handles.wheels.len(),
+            );
+        }
+
+        let (fitness, fell_apart) = fitness_from_block_positions(&blocks(&world));
+        (fitness, fell_apart, behavior.finish(fell_apart))
+    }
+
+    pub fn step(&mut self) {
+        //Same as move_platforms in the obstacles plugin
+        self.steps += 1;
+        let time = self.steps as f32 * self.integration_parameters.dt;
+        for platform in &self.platforms {
+            let [dx, dy] = platform_offset(platform.travel, platform.period, time);
+            let position = Isometry::translation(platform.start.x + dx, platform.start.y + dy);
+            self.bodies[platform.handle].set_next_kinematic_position(position);
+        }
+
+        self.pipeline.step(
+            &self.gravity,
+            &self.integration_parameters,
+            &mut self.islands,
+            &mut self.broad_phase,
+            &mut self.narrow_phase,
+            &mut self.bodies,
+            &mut self.colliders,
+            &mut self.joints,
+            &mut self.ccd_solver,
+            &(),
+            &(),
+        );
+    }
+
+    //X coordinates of the given bodies, in pixels
+    pub fn block_x_positions(&self, handles: &[RigidBodyHandle]) -> Vec<i64> {
+        handles
+            .iter()
+            .map(|h| (self.bodies[*h].translation().x * self.scale).round() as i64)
+            .collect()
+    }
+
+    //Highest Y coordinate of the given bodies, in pixels
+    pub fn max_height(&self, handles: &[RigidBodyHandle]) -> f32 {
+        handles
+            .iter()
+            .map(|h| self.bodies[*h].translation().y * self.scale)
+            .fold(f32::NEG_INFINITY, f32::max)
+    }
+
+    //Wheels that touch anything that isn't part of the vehicle itself
+    pub fn wheels_touching(&self, handles: &VehicleHandles) -> usize {
+        handles
+            .wheels
+            .iter()
+            .filter(|wheel| {
+                self.narrow_phase.contacts_with(**wheel).any(|pair| {
+                    let other = if pair.collider1 == **wheel {
+                        pair.collider2
+                    } else {
+                        pair.collider1
+                    };
+                    pair.has_any_active_contact && !handles.colliders.contains(&other)
+                })
+            })
+            .count()
+    }
+
+    //Same bodies and joints as spawn_obstacles in the obstacles plugin
+    pub fn spawn_obstacles(&mut self, obstacles: &[Obstacle]) {
+        for obstacle in obstacles {
+            let (parts, joints) = obstacle_parts(obstacle);
+
+            let handles = parts
+                .iter()
+                .map(|part| {
+                    let pos = Vector::new(part.position[0], part.position[1]) / self.scale;
+                    let body = RigidBodyBuilder::new(part.body.into())
+                        .translation(pos.x, pos.y)
+                        .can_sleep(false)
+                        .build();
+                    let handle = self.bodies.insert(body);
+
+                    if let Some(shape) = part.shape.collider_shape(self.scale) {
+                        let collider = ColliderBuilder::new(shape)
+                            .density(part.density)
+                            .friction(0.5)
+                            .restitution(0.1)
+                            .build();
+                        self.colliders
+                            .insert_with_parent(collider, handle, &mut self.bodies);
+                    }
+
+                    if let (PartBody::Kinematic, Obstacle::MovingPlatform { travel, period, .. }) =
+                        (part.body, obstacle)
+                    {
+                        self.platforms.push(MovingPlatform {
+                            handle,
+                            start: pos,
+                            travel: [travel[0] / self.scale, travel[1] / self.scale],
+                            period: *period,
+                        });
+                    }
+
+                    handle
+                })
+                .collect::<Vec<_>>();
+
+            for joint in joints {
+                let anchor = |[x, y]: [f32; 2]| Point::new(x, y) / self.scale;
+                let ball_joint = BallJoint::new(anchor(joint.anchor_a), anchor(joint.anchor_b));
+                self.joints.insert(
+                    &mut self.bodies,
+                    handles[joint.a],
+                    handles[joint.b],
+                    ball_joint,
+                );
+            }
+        }
+    }
+
+    //Same layout and joints as setup_panels and setup_joints in vehicle_manager
+    pub fn spawn_vehicle(&mut self, vehicle: &Vehicle, spawn: [f32; 2]) -> VehicleHandles {
+        let mut cells: Array2<Option<(RigidBodyHandle, Point<Real>, Block)>> =
+            Array2::from_elem(vehicle.blocks.raw_dim(), None);
+        let mut colliders = vec![];
+        let mut wheels = vec![];
+
+        for ((y, x), block) in vehicle.blocks.indexed_iter() {
+            let physics = match block_physics(block) {
+                Some(physics) => physics,
+                None => continue, //Air
+            };
+
+            let [block_x, block_y] = block_position(x, y, vehicle.blocks.shape(), self.scale);
+            let pos = Point::new(
+                block_x + spawn[0] / self.scale,
+                block_y + spawn[1] / self.scale,
+            );
+            let body = RigidBodyBuilder::new_dynamic()
+                .translation(pos.x, pos.y)
+                .build();
+            let handle = self.bodies.insert(body);
+
+            let collider = ColliderBuilder::ball(physics.radius)
+                .friction(physics.friction)
+                .restitution(0.1)
+                .build();
+            let collider = self
+                .colliders
+                .insert_with_parent(collider, handle, &mut self.bodies);
+            colliders.push(collider);
+            if *block == Block::Wheel {
+                wheels.push(collider);
+            }
+
+            cells[(y, x)] = Some((handle, pos, *block));
+        }
+
+        let mut connect =
+            |a: &Option<(RigidBodyHandle, Point<Real>, Block)>,
+             b: &Option<(RigidBodyHandle, Point<Real>, Block)>| {
+                if let (Some(a), Some(b)) = (a, b) {
+                    //Note: for ball joints, the second body is assumed to be the wheel
+                    let (fixed, a, b) = match (a.2, b.2) {
+                        (Block::Panel, Block::Panel) => (true, a, b),
+                        (Block::Panel, Block::Wheel) => (false, a, b),
+                        (Block::Wheel, Block::Panel) => (false, b, a),
+                        _ => return, //Else no joint
+                    };
+
+                    let offset = b.1 - a.1;
+                    if fixed {
+                        let joint = FixedJoint::new(
+                            Isometry::identity(),
+                            Isometry::translation(-offset.x, -offset.y),
+                        );
+                        self.joints.insert(&mut self.bodies, a.0, b.0, joint);
+                    } else {
+                        let mut joint =
+                            BallJoint::new(Point::new(offset.x, offset.y), Point::origin());
+                        joint.configure_motor_velocity(-60.0, 0.005);
+                        self.joints.insert(&mut self.bodies, a.0, b.0, joint);
+                    }
+                }
+            };
+
+        for win in cells.windows((2, 1)) {
+            connect(&win[[0, 0]], &win[[1, 0]]);
+        }
+        for win in cells.windows((1, 2)) {
+            connect(&win[[0, 0]], &win[[0, 1]]);
+        }
+
+        VehicleHandles {
+            bodies: cells
+                .iter()
+                .flatten()
+                .map(|(handle, _, _)| *handle)
+                .collect(),
+            colliders,
+            wheels,
+        }
+    }
+}
+
+//Compound shape of the terrain triangles, for the rendered and the headless simulation
+pub fn terrain_collider_shape(triangles: &[Triangle], scale: f32) -> ColliderShape {
+    let triangle_shapes = triangles
+        .iter()
+        .map(|(v1, v2, v3)| {
+            let a = Point::new(v1[0], v1[1]) / scale;
+            let b = Point::new(v2[0], v2[1]) / scale;
+            let c = Point::new(v3[0], v3[1]) / scale;
+
+            (Isometry::identity(), ColliderShape::triangle(a, b, c))
+        })
+        .collect::<Vec<_>>();
+    ColliderShape::compound(triangle_shapes)
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use crate::track::{
+        generator::{generate_track, TrackGeneratorParams},
+        surface_to_triangles,
+    };
+
+    let scale = 100.; //Same as setup_physics
+    let (surface, metadata) = generate_track(&TrackGeneratorParams::default());
+    let terrain = terrain_collider_shape(&surface_to_triangles(&surface), scale);
+
+    //Wheels under a row of panels, so it has something to roll with
+    let mut vehicle = Vehicle::new_empty();
+    vehicle.blocks.row_mut(3).fill(Block::Panel);
+    vehicle.blocks.row_mut(4).fill(Block::Wheel);
+
+    let evaluate = || {
+        HeadlessWorld::evaluate(
+            &terrain,
+            scale,
+            &vehicle,
+            metadata.spawn_point,
+            &metadata.obstacles,
+            5.,
+        )
+    };
+    let (fitness, fell_apart, behavior) = evaluate();
+    info!("headless fitness {}, fell apart: {}", fitness, fell_apart);
+
+    //Every evaluation builds a fresh world, so the same vehicle always gets the same result
+    for _ in 0..3 {
+        let (again, again_fell_apart, again_behavior) = evaluate();
+        assert_eq!(again, fitness);
+        assert_eq!(again_fell_apart, fell_apart);
+        assert_eq!(again_behavior, behavior);
+    }
+}
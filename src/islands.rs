@@ -0,0 +1,225 @@
+use crate::{genetics_simulator::Pop, genome::Genome};
+#[cfg(feature = "app")]
+use bevy_inspector_egui::Inspectable;
+use std::ops::Range;
+
+use log::{debug, error, info, trace, warn}; //IMPORTANT or you won't get any output during tests!
+
+//Island model: the population is split into subpopulations that evolve independently, each with its own settings.
+//Every few generations the best genomes of every island migrate to its neighbors.
+#[cfg_attr(feature = "app", derive(Inspectable))]
+#[derive(Clone, Debug)]
+pub struct IslandParams {
+    pub enabled: bool,
+
+    pub islands: Vec<IslandSettings>, //One entry per island
+
+    pub topology: Topology,
+
+    #[cfg_attr(feature = "app", inspectable(min = 1, max = 50))]
+    pub migration_interval: u32, //Generations between migrations
+
+    #[cfg_attr(feature = "app", inspectable(min = 0, max = 6))]
+    pub migrants: u32, //Best genomes every island sends to each of its neighbors
+}
+
+impl Default for IslandParams {
+    fn default() -> Self {
+        IslandParams {
+            enabled: false,
+            islands: vec![
+                IslandSettings::new(2, 1),
+                IslandSettings::new(4, 1),
+                IslandSettings::new(6, 2),
+                IslandSettings::new(3, 3),
+            ],
+            topology: Topology::Ring,
+            migration_interval: 5,
+            migrants: 1,
+        }
+    }
+}
+
+#[cfg_attr(feature = "app", derive(Inspectable))]
+#[derive(new, Clone, Debug)]
+pub struct IslandSettings {
+    #[cfg_attr(feature = "app", inspectable(min = 2, max = 12))]
+    pub tournament_k: u32, //Capped at the size of the island
+
+    #[cfg_attr(feature = "app", inspectable(min = 1, max = 10))]
+    pub mutation_amount: u32,
+}
+
+impl Default for IslandSettings {
+    fn default() -> Self {
+        IslandSettings::new(4, 1)
+    }
+}
+
+#[cfg_attr(feature = "app", derive(Inspectable))]
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Topology {
+    Ring,           //Island i sends its migrants to island i+1
+    FullyConnected, //Every island sends its migrants to every other island
+}
+
+#[derive(Clone, Debug)]
+pub struct IslandStatistics {
+    pub size: usize,
+    pub avg_fitness: f64,
+    pub max_fitness: f64,
+}
+
+impl IslandParams {
+    //Part of the population that belongs to every island, None if the island model is off.
+    //Islands get a whole number of pairs, so crossover works the same as on the whole population.
+    pub fn ranges(&self, population_size: usize) -> Option<Vec<Range<usize>>> {
+        if !self.enabled || self.islands.len() < 2 {
+            return None;
+        }
+
+        let pairs = population_size / 2;
+        let count = self.islands.len().min(pairs);
+        let mut ranges = vec![];
+        let mut start = 0;
+        for i in 0..count {
+            let size = 2 * (pairs / count + (i < pairs % count) as usize);
+            ranges.push(start..start + size);
+            start += size;
+        }
+        Some(ranges)
+    }
+
+    //Islands that receive the migrants of the given island
+    fn neighbors(&self, island: usize, count: usize) -> Vec<usize> {
+        match self.topology {
+            Topology::Ring => vec![(island + 1) % count],
+            Topology::FullyConnected => (0..count).filter(|i| *i != island).collect(),
+        }
+    }
+}
+
+pub fn island_statistics<G: Genome>(
+    population: &Pop<G>,
+    ranges: &[Range<usize>],
+) -> Vec<IslandStatistics> {
+    ranges
+        .iter()
+        .map(|range| {
+            let fitness = population[range.clone()]
+                .iter()
+                .map(|(_, f)| f.expect("can't calculate island statistics: missing fitness") as f64)
+                .collect::<Vec<_>>();
+            IslandStatistics {
+                size: fitness.len(),
+                avg_fitness: fitness.iter().sum::<f64>() / fitness.len() as f64,
+                max_fitness: fitness.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+            }
+        })
+        .collect()
+}
+
+//Copies of the best genomes of every island replace the worst genomes of its neighbors.
+//Migrants keep their fitness, and at most half of an island gets replaced.
+pub fn migrate<G: Genome>(population: &mut Pop<G>, ranges: &[Range<usize>], params: &IslandParams) {
+    let by_fitness = |range: &Range<usize>, population: &Pop<G>| {
+        let mut indices = range.clone().collect::<Vec<_>>();
+        indices.sort_by_key(|i| std::cmp::Reverse(population[*i].1.expect("missing fitness")));
+        indices
+    };
+
+    let emigrants = ranges
+        .iter()
+        .map(|range| {
+            by_fitness(range, population)
+                .into_iter()
+                .take(params.migrants as usize)
+                .map(|i| population[i].clone())
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let mut immigrants = vec![vec![]; ranges.len()];
+    for (island, migrants) in emigrants.into_iter().enumerate() {
+        for neighbor in params.neighbors(island, ranges.len()) {
+            immigrants[neighbor].extend(migrants.iter().cloned());
+        }
+    }
+
+    for (range, arrivals) in ranges.iter().zip(immigrants) {
+        let worst = by_fitness(range, population).into_iter().rev();
+        let arrivals = arrivals.into_iter().take(range.len() / 2);
+        for (i, migrant) in worst.zip(arrivals) {
+            population[i] = migrant;
+        }
+    }
+
+    info!(
+        "migrated {} genomes from each of {} islands ({:?})",
+        params.migrants,
+        ranges.len(),
+        params.topology
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use crate::vehicle::{Block, Vehicle};
+
+    //Three islands of four, every island has its own design, island i has fitness 10i to 10i+3
+    let designs = [Block::Wheel, Block::Panel, Block::Air]
+        .iter()
+        .map(|b| Vehicle::new_fill_with(*b))
+        .collect::<Vec<_>>();
+    let population = (0..12)
+        .map(|i| {
+            (
+                designs[i / 4].clone(),
+                Some(i as i64 / 4 * 10 + i as i64 % 4),
+            )
+        })
+        .collect::<Pop<Vehicle>>();
+    let mut params = IslandParams {
+        enabled: true,
+        islands: vec![IslandSettings::default(); 3],
+        ..IslandParams::default()
+    };
+    let ranges = params.ranges(12).unwrap();
+    assert_eq!(ranges, vec![0..4, 4..8, 8..12]);
+    assert_eq!(IslandParams::default().ranges(12), None);
+
+    //Islands get whole pairs, the first ones get the leftovers
+    assert_eq!(params.ranges(22).unwrap(), vec![0..8, 8..16, 16..22]);
+    assert_eq!(params.ranges(4).unwrap(), vec![0..2, 2..4]); //Not enough pairs for every island
+
+    let stats = island_statistics(&population, &ranges);
+    assert_eq!(stats[1].size, 4);
+    assert_eq!(stats[1].avg_fitness, 11.5);
+    assert_eq!(stats[2].max_fitness, 23.);
+
+    //Ring: the best of every island replaces the worst of the next one, fitness included
+    let mut ring = population.clone();
+    migrate(&mut ring, &ranges, &params);
+    assert_eq!(ring[0], (designs[2].clone(), Some(23)));
+    assert_eq!(ring[4], (designs[0].clone(), Some(3)));
+    assert_eq!(ring[8], (designs[1].clone(), Some(13)));
+    let moved = ring.iter().zip(&population).filter(|(a, b)| a != b).count();
+    assert_eq!(moved, 3);
+
+    //Fully connected: every island gets migrants from both others, but loses at most half of its genomes
+    params.topology = Topology::FullyConnected;
+    params.migrants = 2;
+    let mut connected = population.clone();
+    migrate(&mut connected, &ranges, &params);
+    assert_eq!(connected[0], (designs[1].clone(), Some(13)));
+    assert_eq!(connected[1], (designs[1].clone(), Some(12)));
+    assert_eq!(connected[2..4], population[2..4]);
+    for range in &ranges {
+        let natives = connected[range.clone()]
+            .iter()
+            .filter(|(g, _)| *g == designs[range.start / 4])
+            .count();
+        assert_eq!(natives, 2);
+    }
+}
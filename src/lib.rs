@@ -0,0 +1,33 @@
+//The genetic algorithm without the bevy app: genomes, selection, fitness bookkeeping and tracks.
+//Build with `--no-default-features` to get only this, without bevy or anything else that renders.
+//The `rapier` feature adds HeadlessWorld, which simulates vehicles with rapier to get their fitness.
+#[macro_use]
+extern crate derive_new;
+
+pub mod diversity;
+pub mod fitness;
+pub mod genealogy;
+pub mod genetics_simulator;
+pub mod genome;
+pub mod hall_of_fame;
+#[cfg(feature = "rapier")]
+pub mod headless_world;
+pub mod islands;
+pub mod logger;
+pub mod map_elites;
+pub mod niching;
+pub mod novelty;
+pub mod nsga2;
+pub mod speciation;
+pub mod steady_state;
+pub mod track;
+pub mod track_evolution;
+pub mod vehicle;
+pub mod vehicle_states;
+
+//Setup logger, this runs only when testing
+#[cfg(test)]
+#[ctor::ctor]
+fn init() {
+    crate::logger::setup_test_logger();
+}
@@ -0,0 +1,28 @@
+#[cfg(target_arch = "wasm32")]
+use log::Level;
+
+pub fn setup_test_logger() {
+    //If on WASM, use console_log, else use fern
+
+    #[cfg(target_arch = "wasm32")]
+    console_log::init_with_level(Level::Info)
+        .unwrap_or_else(|_| println!("couldn't init web logger"));
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fern::Dispatch::new()
+        .format(|out, message, record| {
+            out.finish(format_args!(
+                "{}[{}:{}][{}] {}",
+                chrono::Local::now().format("[%Y-%m-%d][%H:%M:%S]"),
+                record.target(),
+                record.line().map_or("???".into(), |x| x.to_string()),
+                record.level(),
+                message
+            ))
+        })
+        .level(log::LevelFilter::Warn)
+        .level_for("vehicle_evolver_deluxe", log::LevelFilter::Info)
+        .chain(std::io::stdout())
+        .apply()
+        .unwrap();
+}
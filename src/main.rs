@@ -9,18 +9,22 @@ use bevy_egui::EguiPlugin;
 use bevy_prototype_lyon::prelude::*;
 use bevy_rapier2d::prelude::*;
 use plugins::*;
-use utility::setup_test_logger;
+use vehicle_evolver_deluxe::logger::setup_test_logger;
 
 #[cfg(not(target_arch = "wasm32"))]
 use bevy_inspector_egui::WorldInspectorPlugin;
 
 use log::{debug, error, info, trace, warn}; //IMPORTANT or you won't get any output during tests!
 
-mod genetics_simulator;
 mod plugins;
 mod utility;
-mod vehicle;
-mod vehicle_states;
+
+//The app is a thin layer on top of the library, these make `crate::vehicle` etc. work in the app too
+use vehicle_evolver_deluxe::{
+    fitness, genealogy, genetics_simulator, hall_of_fame, headless_world, islands, map_elites,
+    niching, novelty, nsga2, speciation, steady_state, track, track_evolution, vehicle,
+    vehicle_states,
+};
 
 fn main() {
     setup_test_logger();
@@ -56,24 +60,46 @@ fn main() {
     #[cfg(target_arch = "wasm32")]
     app.add_plugin(bevy_webgl2::WebGL2Plugin);
 
+    //Usage: vehicle_evolver_deluxe [--track <file.json|file.png|file.svg>]
+    let args = std::env::args().collect::<Vec<_>>();
+    if let Some(i) = args.iter().position(|arg| arg == "--track") {
+        let track_file = args.get(i + 1).expect("--track needs a file").clone();
+        info!("using track {}", track_file);
+
+        //Must be inserted before the GeneticsPlugin, or the defaults are used
+        app.insert_resource(genetics::SimulationParams {
+            track_source: track::TrackSource::File,
+            track_file,
+            ..Default::default()
+        });
+    }
+
     #[cfg(not(target_arch = "wasm32"))]
     {
         let show_debug_inspector = false; //Disabled because it's really slow
         if show_debug_inspector {
             app.add_plugin(WorldInspectorPlugin::new());
         }
+
+        app.add_plugin(parallel_evaluation::ParallelEvaluationPlugin);
     }
 
     app.add_plugin(ShapePlugin)
         .add_plugin(EguiPlugin)
         .add_plugin(camera::CameraPlugin)
         .add_plugin(RapierPhysicsPlugin::<NoUserData>::default())
+        .add_plugin(simulation_clock::SimulationClockPlugin)
         .add_plugin(vehicle_manager::VehicleSpawnerPlugin)
         .add_plugin(genetics::GeneticsPlugin)
+        .add_plugin(plugins::map_elites::MapElitesPlugin) //`map_elites` alone is the library module
+        .add_plugin(plugins::hall_of_fame::HallOfFamePlugin) //Same for `hall_of_fame`
+        .add_plugin(plugins::genealogy::GenealogyPlugin) //And `genealogy`
         .add_plugin(RapierRenderPlugin)
         .add_plugin(terrain_mesh::TerrainMeshPlugin)
+        .add_plugin(obstacles::ObstaclePlugin) //Must come after RapierPhysicsPlugin
         .add_plugin(background::BackgroundPlugin)
         .add_plugin(spawn_indicator::SpawnIndicatorPlugin)
+        .add_plugin(terrain_editor::TerrainEditorPlugin)
         .add_startup_system(setup.system())
         .add_system(fix_textures.system())
         .add_startup_system(hide_loading_text.system())
@@ -138,5 +164,5 @@ fn fix_textures(
 #[cfg(test)]
 #[ctor::ctor]
 fn init() {
-    crate::utility::setup_test_logger();
+    setup_test_logger();
 }
@@ -0,0 +1,307 @@
+use crate::{
+    genealogy::{self, Genealogy},
+    vehicle::{Block, Vehicle, VEHICLE_SHAPE},
+};
+#[cfg(feature = "app")]
+use bevy_inspector_egui::Inspectable;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use log::{debug, error, info, trace, warn}; //IMPORTANT or you won't get any output during tests!
+
+//Properties of a design that the archive is indexed by
+#[cfg_attr(feature = "app", derive(Inspectable))]
+#[derive(PartialEq, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum Feature {
+    Wheels,
+    Panels,
+    Width,  //Columns between the leftmost and the rightmost block
+    Height, //Rows between the top and the bottom block
+}
+
+impl Feature {
+    pub fn measure(&self, vehicle: &Vehicle) -> usize {
+        let solid = || {
+            vehicle
+                .blocks
+                .indexed_iter()
+                .filter(|(_, block)| **block != Block::Air)
+                .map(|(cell, _)| cell)
+        };
+        let extent = |values: Vec<usize>| match (values.iter().min(), values.iter().max()) {
+            (Some(min), Some(max)) => max - min + 1,
+            _ => 0,
+        };
+
+        match self {
+            Feature::Wheels => vehicle
+                .blocks
+                .iter()
+                .filter(|b| **b == Block::Wheel)
+                .count(),
+            Feature::Panels => vehicle
+                .blocks
+                .iter()
+                .filter(|b| **b == Block::Panel)
+                .count(),
+            Feature::Width => extent(solid().map(|(_, x)| x).collect()),
+            Feature::Height => extent(solid().map(|(y, _)| y).collect()),
+        }
+    }
+
+    pub fn max(&self) -> usize {
+        match self {
+            Feature::Wheels | Feature::Panels => VEHICLE_SHAPE.0 * VEHICLE_SHAPE.1,
+            Feature::Width => VEHICLE_SHAPE.1,
+            Feature::Height => VEHICLE_SHAPE.0,
+        }
+    }
+
+    //Number of cells along this axis, there's no point in having more cells than values
+    pub fn bins(&self, bins: usize) -> usize {
+        bins.clamp(1, self.max() + 1)
+    }
+
+    pub fn bin(&self, vehicle: &Vehicle, bins: usize) -> usize {
+        let bins = self.bins(bins);
+        (self.measure(vehicle) * bins / (self.max() + 1)).min(bins - 1)
+    }
+
+    //Range of values that end up in the given cell, e.g. "4-7"
+    pub fn bin_label(&self, bin: usize, bins: usize) -> String {
+        let bins = self.bins(bins);
+        let values = (0..=self.max())
+            .filter(|value| value * bins / (self.max() + 1) == bin)
+            .collect::<Vec<_>>();
+        match (values.first(), values.last()) {
+            (Some(first), Some(last)) if first == last => format!("{}", first),
+            (Some(first), Some(last)) => format!("{}-{}", first, last),
+            _ => "".to_owned(),
+        }
+    }
+}
+
+//MAP-Elites: instead of one population that converges on one design, keep the best vehicle of every kind
+#[cfg_attr(feature = "app", derive(Inspectable))]
+#[derive(Clone, Debug)]
+pub struct MapElitesParams {
+    pub breed_from_archive: bool, //Every generation is made of mutated elites instead of the selected population
+
+    pub x_feature: Feature,
+    pub y_feature: Feature,
+
+    #[cfg_attr(feature = "app", inspectable(min = 2, max = 49))]
+    pub bins: u32, //Cells per axis, at most one per possible value
+}
+
+impl Default for MapElitesParams {
+    fn default() -> Self {
+        MapElitesParams {
+            breed_from_archive: false,
+            x_feature: Feature::Wheels,
+            y_feature: Feature::Panels,
+            bins: 10,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Elite {
+    pub vehicle: Vehicle,
+    pub fitness: i64,
+    pub cell: [usize; 2],  //x, y
+    pub generation: usize, //When it got into the archive
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EliteArchive {
+    features: [Feature; 2],
+    bins: usize,
+    elites: Vec<Elite>, //At most one per cell
+}
+
+impl EliteArchive {
+    pub fn new(params: &MapElitesParams) -> Self {
+        EliteArchive {
+            features: [params.x_feature, params.y_feature],
+            bins: params.bins as usize,
+            elites: vec![],
+        }
+    }
+
+    pub fn features(&self) -> [Feature; 2] {
+        self.features
+    }
+
+    //Cells along the x and y axis
+    pub fn size(&self) -> [usize; 2] {
+        [
+            self.features[0].bins(self.bins),
+            self.features[1].bins(self.bins),
+        ]
+    }
+
+    pub fn bins(&self) -> usize {
+        self.bins
+    }
+
+    pub fn elites(&self) -> &[Elite] {
+        &self.elites
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elites.is_empty()
+    }
+
+    //Fraction of the cells that have an elite
+    pub fn coverage(&self) -> f64 {
+        let [x, y] = self.size();
+        self.elites.len() as f64 / (x * y) as f64
+    }
+
+    pub fn get(&self, cell: [usize; 2]) -> Option<&Elite> {
+        self.elites.iter().find(|elite| elite.cell == cell)
+    }
+
+    pub fn cell(&self, vehicle: &Vehicle) -> [usize; 2] {
+        [
+            self.features[0].bin(vehicle, self.bins),
+            self.features[1].bin(vehicle, self.bins),
+        ]
+    }
+
+    //The vehicle becomes the elite of its cell if the cell is empty or it beats the current elite.
+    //Returns whether it got in.
+    pub fn insert(&mut self, vehicle: &Vehicle, fitness: i64, generation: usize) -> bool {
+        let cell = self.cell(vehicle);
+        let elite = Elite {
+            vehicle: vehicle.clone(),
+            fitness,
+            cell,
+            generation,
+        };
+
+        match self.elites.iter_mut().find(|elite| elite.cell == cell) {
+            Some(old) if old.fitness >= fitness => false,
+            Some(old) => {
+                *old = elite;
+                true
+            }
+            None => {
+                self.elites.push(elite);
+                true
+            }
+        }
+    }
+
+    //Same elites sorted into a different grid. Elites that end up in the same cell compete again.
+    pub fn regrid(&self, params: &MapElitesParams) -> Self {
+        let mut archive = EliteArchive::new(params);
+        for elite in &self.elites {
+            archive.insert(&elite.vehicle, elite.fitness, elite.generation);
+        }
+        archive
+    }
+
+    //Children of randomly picked elites, like in MAP-Elites every cell has the same chance to be picked
+    pub fn offspring(
+        &self,
+        n: usize,
+        mutation_amount: usize,
+        genealogy: &mut Genealogy<Vehicle>,
+        generation: usize,
+    ) -> Vec<Vehicle> {
+        let mut rng = rand::thread_rng();
+        (0..n)
+            .map(|_| {
+                let a = &self.elites.choose(&mut rng).expect("empty archive").vehicle;
+                let b = &self.elites.choose(&mut rng).expect("empty archive").vehicle;
+                let (birth, _) = genealogy::breed(a, b, mutation_amount);
+                genealogy.record_birth(&birth, generation);
+                birth.child
+            })
+            .collect()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("couldn't serialize archive: {}", e))?;
+        std::fs::write(path, json)
+            .map_err(|e| format!("couldn't save {}: {}", path.display(), e))?;
+
+        info!("saved {} elites to {}", self.elites.len(), path.display());
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+        let archive: EliteArchive = serde_json::from_str(&json)
+            .map_err(|e| format!("invalid archive file {}: {}", path.display(), e))?;
+
+        info!(
+            "loaded {} elites from {}",
+            archive.elites.len(),
+            path.display()
+        );
+        Ok(archive)
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    let params = MapElitesParams::default();
+    let mut archive = EliteArchive::new(&params);
+
+    let wheels = Vehicle::new_fill_with(Block::Wheel);
+    let panels = Vehicle::new_fill_with(Block::Panel);
+    assert!(archive.insert(&wheels, 100, 0));
+    assert!(archive.insert(&panels, 50, 0));
+    assert!(!archive.insert(&wheels, 80, 1)); //Worse than the elite of its cell
+    assert_eq!(archive.elites().len(), 2);
+    assert_eq!(archive.cell(&wheels), [9, 0]);
+    assert_eq!(archive.get([9, 0]).unwrap().fitness, 100);
+    assert_eq!(Feature::Wheels.bin_label(9, 10), "45-48");
+
+    //Both fill the whole grid, so they compete for the same cell
+    let by_size = archive.regrid(&MapElitesParams {
+        x_feature: Feature::Width,
+        y_feature: Feature::Height,
+        ..params
+    });
+    assert_eq!(by_size.elites().len(), 1);
+    assert_eq!(by_size.elites()[0].fitness, 100);
+
+    let json = serde_json::to_string(&archive).unwrap();
+    let loaded: EliteArchive = serde_json::from_str(&json).unwrap();
+    assert_eq!(loaded.elites().len(), 2);
+    let mut genealogy = Genealogy::default();
+    let offspring = loaded.offspring(4, 1, &mut genealogy, 0);
+    assert_eq!(offspring.len(), 4);
+    assert!(offspring.iter().all(|child| genealogy.id(child).is_some()));
+
+    //The simulator keeps its statistics and genealogy while the archive breeds. Nothing is bred and thrown away.
+    use crate::{
+        fitness::GlobalFitnessMap,
+        genetics_simulator::{EvolutionParams, GeneticsSimulator},
+    };
+    let map: GlobalFitnessMap = std::sync::Arc::new(dashmap::DashMap::new());
+    map.entry(wheels.clone()).or_default().push(0, 100, 0);
+    map.entry(panels.clone()).or_default().push(0, 50, 0);
+    let params = EvolutionParams::default();
+    let mut sim = GeneticsSimulator::from_population(vec![wheels, panels]);
+    sim.fill_in_fitness(&map, &params);
+    sim.step_from(&params, |genealogy, generation| {
+        assert_eq!(generation, 1);
+        loaded.offspring(2, 1, genealogy, generation)
+    });
+    assert_eq!(sim.genealogy().len(), 4); //Both designs and their two children
+    assert_eq!(sim.get_generational_statistics()[0].max_fitness, 100.);
+    let population = sim.get_population();
+    assert_eq!(population.len(), 2);
+    assert!(population
+        .iter()
+        .all(|(child, fitness)| fitness.is_none() && sim.genealogy().id(child) >= Some(2)));
+}
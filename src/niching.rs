@@ -0,0 +1,165 @@
+use crate::{
+    genealogy::{self, Genealogy},
+    genetics_simulator::Pop,
+    genome::Genome,
+};
+#[cfg(feature = "app")]
+use bevy_inspector_egui::Inspectable;
+use rand::seq::SliceRandom;
+
+use log::{debug, error, info, trace, warn}; //IMPORTANT or you won't get any output during tests!
+
+//Ways to keep many copies of one design from taking over the population, so several designs can evolve side by side
+#[cfg_attr(feature = "app", derive(Inspectable))]
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Niching {
+    Off,
+    FitnessSharing, //Genomes within sharing_radius of each other split their fitness during selection
+    DeterministicCrowding, //Children only replace the parent they're most similar to, and only if they're fitter
+    Speciation, //Genomes are grouped into species that mostly breed among themselves, see SpeciationParams
+}
+
+//Fitness used for selection when fitness sharing is on: every fitness is divided by the number of genomes
+//in its niche, so a design that was copied 10 times is only as attractive as a unique one that's 10x worse.
+//Every genome needs a fitness. Fitness is shifted to be positive first, so dividing it always makes it worse.
+pub fn shared_fitness<G: Genome>(population: &Pop<G>, radius: f64) -> Pop<G> {
+    let fitness = population
+        .iter()
+        .map(|(_, f)| f.expect("can't share fitness: missing fitness"))
+        .collect::<Vec<_>>();
+    let min = fitness.iter().min().copied().unwrap_or(0);
+
+    population
+        .iter()
+        .zip(&fitness)
+        .map(|((genome, _), f)| {
+            let niche_count = population
+                .iter()
+                .map(|(other, _)| sharing(genome.distance(other), radius))
+                .sum::<f64>(); //At least 1, since a genome is in its own niche
+
+            let shared = (f - min + 1) as f64 / niche_count;
+            (genome.clone(), Some(shared.round() as i64))
+        })
+        .collect()
+}
+
+//Triangular sharing function: 1 for identical genomes, 0 from `radius` onwards
+fn sharing(distance: f64, radius: f64) -> f64 {
+    if distance < radius {
+        1. - distance / radius
+    } else {
+        0.
+    }
+}
+
+//Pairs up the population at random and makes two children per pair. Every child is matched with the parent
+//it resembles most, the returned parents are in the same order as the children.
+pub fn crowding_children<G: Genome>(
+    population: &Pop<G>,
+    mutation_amount: usize,
+    genealogy: &mut Genealogy<G>,
+    generation: usize,
+) -> (Pop<G>, Pop<G>) {
+    let mut rng = rand::thread_rng();
+    let mut shuffled = population.clone();
+    shuffled.shuffle(&mut rng);
+
+    let mut children = vec![];
+    let mut parents = vec![];
+    for pair in shuffled.chunks(2) {
+        let (a, b) = match pair {
+            [a, b] => (a, b),
+            [a] => (a, a), //Odd one out gets a mutated copy of itself
+            _ => unreachable!(),
+        };
+
+        let (b1, b2) = genealogy::breed(&a.0, &b.0, mutation_amount);
+        genealogy.record_birth(&b1, generation);
+        if pair.len() == 2 {
+            genealogy.record_birth(&b2, generation);
+        }
+        let (mut c1, mut c2) = (b1.child, b2.child);
+
+        let straight = a.0.distance(&c1) + b.0.distance(&c2);
+        let crossed = a.0.distance(&c2) + b.0.distance(&c1);
+        if crossed < straight {
+            std::mem::swap(&mut c1, &mut c2);
+        }
+
+        children.push((c1, None));
+        parents.push(a.clone());
+        if pair.len() == 2 {
+            children.push((c2, None));
+            parents.push(b.clone());
+        }
+    }
+
+    (children, parents)
+}
+
+//Once the children are evaluated, each of them replaces its parent only if it's at least as fit
+pub fn crowding_survivors<G: Genome>(children: &Pop<G>, parents: &Pop<G>) -> Pop<G> {
+    let mut replaced = 0;
+    let survivors = children
+        .iter()
+        .zip(parents)
+        .map(|(child, parent)| {
+            if child.1 >= parent.1 {
+                replaced += 1;
+                child.clone()
+            } else {
+                parent.clone()
+            }
+        })
+        .collect();
+
+    info!(
+        "deterministic crowding: {}/{} children replaced their parent",
+        replaced,
+        children.len()
+    );
+    survivors
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use crate::vehicle::{Block, Vehicle};
+
+    let wheels = Vehicle::new_fill_with(Block::Wheel);
+    let panels = Vehicle::new_fill_with(Block::Panel);
+    let air = Vehicle::new_fill_with(Block::Air);
+
+    //Four copies of the best design share their niche, so the unique runner-up becomes the most attractive
+    let mut population = vec![(wheels.clone(), Some(100)); 4];
+    population.push((panels.clone(), Some(90)));
+    population.push((air.clone(), Some(0)));
+    let shared = shared_fitness(&population, 6.);
+    assert_eq!(shared[0], (wheels.clone(), Some(25))); //(100 - 0 + 1) / 4
+    assert_eq!(shared[4], (panels.clone(), Some(91)));
+    assert_eq!(shared[5], (air.clone(), Some(1)));
+    let best = |pop: &Pop<Vehicle>| pop.iter().max_by_key(|(_, f)| *f).unwrap().0.clone();
+    assert_eq!(best(&population), wheels);
+    assert_eq!(best(&shared), panels);
+
+    //Deterministic crowding: every child competes with the parent it resembles most
+    let mut genealogy = Genealogy::default();
+    let parents = vec![(wheels.clone(), Some(100)), (panels.clone(), Some(50))];
+    for _ in 0..10 {
+        let (children, matched) = crowding_children(&parents, 2, &mut genealogy, 1);
+        assert_eq!(children.len(), 2);
+        let straight =
+            children[0].0.distance(&matched[0].0) + children[1].0.distance(&matched[1].0);
+        let crossed = children[0].0.distance(&matched[1].0) + children[1].0.distance(&matched[0].0);
+        assert!(straight <= crossed);
+        assert!(children
+            .iter()
+            .all(|(child, _)| genealogy.id(child).is_some()));
+    }
+
+    //Children only replace their parent if they're at least as fit
+    let children = vec![(air.clone(), Some(100)), (air, Some(10))];
+    let survivors = crowding_survivors(&children, &parents);
+    assert_eq!(survivors, vec![children[0].clone(), parents[1].clone()]);
+}
@@ -0,0 +1,247 @@
+#[cfg(feature = "app")]
+use bevy_inspector_egui::Inspectable;
+
+use log::{debug, error, info, trace, warn}; //IMPORTANT or you won't get any output during tests!
+
+const HEIGHT_WEIGHT: f64 = 3.; //Vehicles climb much less than they drive, so height differences count more
+const CONTACT_WEIGHT: f64 = 2000.; //Driving on all wheels vs. flying through the air
+const FELL_APART_WEIGHT: f64 = 2000.;
+const MAX_ARCHIVE_SIZE: usize = 1000; //Oldest behaviors are forgotten first
+
+//Behavior of a vehicle as a point in "pixel-like" units, so distances between behaviors can be mixed with fitness
+pub type Descriptor = [f64; 4];
+
+//What a vehicle did during one evaluation, regardless of how good that was
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Behavior {
+    pub distance: f32, //Pixels from the spawn point at the end, without the punishment for falling apart
+    pub max_height: f32, //Pixels above the spawn point
+    pub ground_contact: f32, //Average fraction of the wheels touching the ground, 0 for vehicles without wheels
+    pub fell_apart: bool,
+}
+
+impl Behavior {
+    pub fn descriptor(&self) -> Descriptor {
+        [
+            self.distance as f64,
+            self.max_height as f64 * HEIGHT_WEIGHT,
+            self.ground_contact as f64 * CONTACT_WEIGHT,
+            if self.fell_apart {
+                FELL_APART_WEIGHT
+            } else {
+                0.
+            },
+        ]
+    }
+}
+
+//Collects the samples a Behavior is made of while a vehicle is being simulated
+#[derive(Default, Clone, PartialEq, Debug)]
+pub struct BehaviorRecorder {
+    samples: u32,
+    distance: f32,
+    max_height: Option<f32>,
+    contact_sum: f32,
+}
+
+impl BehaviorRecorder {
+    //Distance and height are in pixels relative to the spawn point
+    pub fn observe(&mut self, distance: f32, height: f32, wheels_touching: usize, wheels: usize) {
+        self.samples += 1;
+        self.distance = distance;
+        self.max_height = Some(self.max_height.map_or(height, |max| max.max(height)));
+        if wheels > 0 {
+            self.contact_sum += wheels_touching as f32 / wheels as f32;
+        }
+    }
+
+    pub fn finish(&self, fell_apart: bool) -> Behavior {
+        Behavior {
+            distance: self.distance,
+            max_height: self.max_height.unwrap_or(0.),
+            ground_contact: self.contact_sum / self.samples.max(1) as f32,
+            fell_apart,
+        }
+    }
+}
+
+//What selection optimizes
+#[cfg_attr(feature = "app", derive(Inspectable))]
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Objective {
+    Fitness, //Distance driven
+    Novelty, //Distance from the behaviors in the archive and the rest of the population
+    Blend,   //novelty_weight * novelty + (1 - novelty_weight) * fitness
+}
+
+#[cfg_attr(feature = "app", derive(Inspectable))]
+#[derive(Clone, Debug)]
+pub struct NoveltyParams {
+    pub objective: Objective,
+
+    #[cfg_attr(feature = "app", inspectable(min = 0., max = 1.))]
+    pub novelty_weight: f32, //Only used by Objective::Blend
+
+    #[cfg_attr(feature = "app", inspectable(min = 1, max = 30))]
+    pub nearest_neighbors: u32, //Novelty is the mean distance to this many closest behaviors
+
+    #[cfg_attr(feature = "app", inspectable(min = 0, max = 10))]
+    pub archive_per_generation: u32, //The most novel behaviors of every generation are remembered
+}
+
+impl Default for NoveltyParams {
+    fn default() -> Self {
+        NoveltyParams {
+            objective: Objective::Fitness,
+            novelty_weight: 0.5,
+            nearest_neighbors: 10,
+            archive_per_generation: 2,
+        }
+    }
+}
+
+impl NoveltyParams {
+    //Fitness used for selection, novelty is already in the same units as the fitness
+    pub fn objective(&self, fitness: i64, novelty: f64) -> i64 {
+        let weight = self.novelty_weight as f64;
+        let objective = match self.objective {
+            Objective::Fitness => fitness as f64,
+            Objective::Novelty => novelty,
+            Objective::Blend => weight * novelty + (1. - weight) * fitness as f64,
+        };
+        objective.round() as i64
+    }
+}
+
+//Behaviors of earlier generations. Without it the population could go round in circles, always rediscovering
+//behaviors that are only novel compared to the current population.
+#[derive(Default)]
+pub struct NoveltyArchive {
+    descriptors: Vec<Descriptor>,
+}
+
+impl NoveltyArchive {
+    pub fn len(&self) -> usize {
+        self.descriptors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.descriptors.is_empty()
+    }
+
+    //Mean distance of every descriptor to its k nearest neighbors, in the archive and the rest of the population.
+    //Genomes without a descriptor (e.g. never simulated) aren't novel at all.
+    pub fn novelty(&self, population: &[Option<Descriptor>], k: usize) -> Vec<f64> {
+        population
+            .iter()
+            .enumerate()
+            .map(|(i, descriptor)| {
+                let descriptor = match descriptor {
+                    Some(descriptor) => descriptor,
+                    None => return 0.,
+                };
+
+                let others = population
+                    .iter()
+                    .enumerate()
+                    .filter(|(j, _)| *j != i)
+                    .filter_map(|(_, other)| other.as_ref())
+                    .chain(&self.descriptors);
+                let mut distances = others
+                    .map(|other| distance(descriptor, other))
+                    .collect::<Vec<_>>();
+                distances.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+                let nearest = &distances[..k.max(1).min(distances.len())];
+                if nearest.is_empty() {
+                    0.
+                } else {
+                    nearest.iter().sum::<f64>() / nearest.len() as f64
+                }
+            })
+            .collect()
+    }
+
+    //Remembers the `count` most novel behaviors
+    pub fn add(&mut self, population: &[Option<Descriptor>], novelty: &[f64], count: usize) {
+        let mut by_novelty = population
+            .iter()
+            .zip(novelty)
+            .filter_map(|(descriptor, novelty)| descriptor.map(|d| (d, *novelty)))
+            .collect::<Vec<_>>();
+        by_novelty.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+
+        self.descriptors
+            .extend(by_novelty.into_iter().take(count).map(|(d, _)| d));
+        if self.descriptors.len() > MAX_ARCHIVE_SIZE {
+            let excess = self.descriptors.len() - MAX_ARCHIVE_SIZE;
+            self.descriptors.drain(..excess);
+        }
+        debug!("novelty archive has {} behaviors", self.descriptors.len());
+    }
+}
+
+fn distance(a: &Descriptor, b: &Descriptor) -> f64 {
+    a.iter()
+        .zip(b)
+        .map(|(x, y)| (x - y).powi(2))
+        .sum::<f64>()
+        .sqrt()
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    let behavior = Behavior {
+        distance: 10.,
+        max_height: 2.,
+        ground_contact: 0.5,
+        fell_apart: true,
+    };
+    assert_eq!(behavior.descriptor(), [10., 6., 1000., 2000.]);
+
+    let mut recorder = BehaviorRecorder::default();
+    recorder.observe(5., 10., 1, 2);
+    recorder.observe(20., -5., 2, 2);
+    let recorded = recorder.finish(false);
+    assert_eq!((recorded.distance, recorded.max_height), (20., 10.));
+    assert_eq!(recorded.ground_contact, 0.75);
+    assert_eq!(BehaviorRecorder::default().finish(true).ground_contact, 0.);
+
+    //Vehicles that only differ in how far they drove
+    let at = |distance: f64| Some([distance, 0., 0., 0.]);
+    let population = [at(0.), at(10.), at(30.), None];
+    let mut archive = NoveltyArchive::default();
+    assert_eq!(archive.novelty(&population, 1), vec![10., 10., 20., 0.]);
+    let novelty = archive.novelty(&population, 2);
+    assert_eq!(novelty, vec![20., 15., 25., 0.]);
+    assert_eq!(archive.novelty(&population, 10), novelty); //Only two neighbors to compare with
+
+    //The most novel behaviors are remembered, genomes without a behavior never are
+    archive.add(&population, &novelty, 2);
+    assert_eq!(
+        archive.descriptors,
+        vec![[30., 0., 0., 0.], [0., 0., 0., 0.]]
+    );
+    archive.add(&[None], &[100.], 1);
+    assert_eq!(archive.len(), 2);
+
+    //Behaviors are compared with the archive too
+    assert_eq!(archive.novelty(&[at(10.)], 1), vec![10.]);
+    assert_eq!(archive.novelty(&[at(10.)], 2), vec![15.]);
+
+    //Once the archive is full, the oldest behaviors are forgotten first
+    let crowd = (0..MAX_ARCHIVE_SIZE)
+        .map(|i| at(1000. + i as f64))
+        .collect::<Vec<_>>();
+    archive.add(&crowd, &vec![1.; MAX_ARCHIVE_SIZE], MAX_ARCHIVE_SIZE);
+    assert_eq!(archive.len(), MAX_ARCHIVE_SIZE);
+    assert_eq!(archive.novelty(&[at(0.)], 1), vec![1000.]);
+
+    let mut params = NoveltyParams::default();
+    assert_eq!(params.objective(100, 50.4), 100);
+    params.objective = Objective::Novelty;
+    assert_eq!(params.objective(100, 50.4), 50);
+    params.objective = Objective::Blend;
+    assert_eq!(params.objective(100, 50.4), 75);
+}
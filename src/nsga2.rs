@@ -0,0 +1,244 @@
+use crate::{
+    fitness::FitnessRecord,
+    vehicle::{Block, Vehicle},
+};
+use rand::seq::SliceRandom;
+use std::cmp::Ordering;
+
+use log::{debug, error, info, trace, warn}; //IMPORTANT or you won't get any output during tests!
+
+//Scores of a genome on every objective, all of them are maximized
+pub type Objectives = Vec<f64>;
+
+//Names of the objectives of vehicle_objectives, in order
+pub const VEHICLE_OBJECTIVES: [&str; 3] = ["Distance", "Blocks", "Integrity"];
+
+//Distance without the punishment for falling apart, minus the number of blocks (cost), and the fraction of
+//evaluations the vehicle stayed in one piece. None if no behavior was recorded.
+pub fn vehicle_objectives(vehicle: &Vehicle, record: &FitnessRecord) -> Option<Objectives> {
+    let blocks = vehicle.blocks.iter().filter(|b| **b != Block::Air).count();
+    Some(vec![
+        record.distance()?,
+        -(blocks as f64),
+        record.integrity()?,
+    ])
+}
+
+//a is at least as good as b on every objective and better on at least one
+pub fn dominates(a: &[f64], b: &[f64]) -> bool {
+    a.iter().zip(b).all(|(x, y)| x >= y) && a.iter().zip(b).any(|(x, y)| x > y)
+}
+
+//Fast non-dominated sort: the first front is everything nothing dominates, the second front is what only the first
+//front dominates, and so on. Returns the indices in every front.
+pub fn non_dominated_sort(objectives: &[Objectives]) -> Vec<Vec<usize>> {
+    let n = objectives.len();
+    let mut dominated = vec![vec![]; n]; //Indices every genome dominates
+    let mut domination_count = vec![0; n]; //Number of genomes that dominate it
+
+    for i in 0..n {
+        for j in 0..n {
+            if dominates(&objectives[i], &objectives[j]) {
+                dominated[i].push(j);
+            } else if dominates(&objectives[j], &objectives[i]) {
+                domination_count[i] += 1;
+            }
+        }
+    }
+
+    let mut fronts = vec![];
+    let mut front = (0..n)
+        .filter(|i| domination_count[*i] == 0)
+        .collect::<Vec<_>>();
+    while !front.is_empty() {
+        let mut next = vec![];
+        for i in &front {
+            for j in &dominated[*i] {
+                domination_count[*j] -= 1;
+                if domination_count[*j] == 0 {
+                    next.push(*j);
+                }
+            }
+        }
+        fronts.push(front);
+        front = next;
+    }
+
+    fronts
+}
+
+//How far apart the neighbors of every genome in the front are, summed over all objectives. The extremes of every
+//objective get infinity so they're always kept.
+pub fn crowding_distance(objectives: &[Objectives], front: &[usize]) -> Vec<f64> {
+    let mut distance = vec![0.; front.len()];
+    let count = front.first().map_or(0, |i| objectives[*i].len());
+
+    for m in 0..count {
+        let values = objective_values(objectives, front, m);
+        let mut order = (0..front.len()).collect::<Vec<_>>();
+        order.sort_by(|a, b| {
+            values[*a]
+                .partial_cmp(&values[*b])
+                .unwrap_or(Ordering::Equal)
+        });
+
+        let (first, last) = (order[0], order[order.len() - 1]);
+        distance[first] = f64::INFINITY;
+        distance[last] = f64::INFINITY;
+
+        let range = values[last] - values[first];
+        if range <= 0. {
+            continue;
+        }
+        for w in order.windows(3) {
+            let (prev, this, next) = (w[0], w[1], w[2]);
+            distance[this] += (values[next] - values[prev]) / range;
+        }
+    }
+
+    distance
+}
+
+//Scores of every genome in the front on objective m
+fn objective_values(objectives: &[Objectives], front: &[usize], m: usize) -> Vec<f64> {
+    front.iter().map(|i| objectives[*i][m]).collect()
+}
+
+//Front (0 is the best) and crowding distance of a genome
+#[derive(Clone, Copy, Debug)]
+pub struct Rank {
+    pub front: usize,
+    pub crowding: f64,
+}
+
+impl Rank {
+    //The crowded comparison operator: a lower front wins, within a front the less crowded genome wins
+    pub fn compare(&self, other: &Rank) -> Ordering {
+        other.front.cmp(&self.front).then(
+            self.crowding
+                .partial_cmp(&other.crowding)
+                .unwrap_or(Ordering::Equal),
+        )
+    }
+}
+
+pub fn rank(objectives: &[Objectives]) -> Vec<Rank> {
+    let mut ranks = vec![
+        Rank {
+            front: 0,
+            crowding: 0.
+        };
+        objectives.len()
+    ];
+    for (f, front) in non_dominated_sort(objectives).iter().enumerate() {
+        for (i, crowding) in front.iter().zip(crowding_distance(objectives, front)) {
+            ranks[*i] = Rank { front: f, crowding };
+        }
+    }
+    ranks
+}
+
+//The best n genomes: whole fronts as long as they fit, then the least crowded genomes of the next front
+pub fn select_survivors(objectives: &[Objectives], n: usize) -> Vec<usize> {
+    let mut survivors = vec![];
+    for front in non_dominated_sort(objectives) {
+        if survivors.len() + front.len() <= n {
+            survivors.extend(front);
+            continue;
+        }
+
+        let crowding = crowding_distance(objectives, &front);
+        let mut order = (0..front.len()).collect::<Vec<_>>();
+        order.sort_by(|a, b| {
+            crowding[*b]
+                .partial_cmp(&crowding[*a])
+                .unwrap_or(Ordering::Equal)
+        });
+        let missing = n - survivors.len();
+        survivors.extend(order.into_iter().take(missing).map(|i| front[i]));
+        break;
+    }
+    survivors
+}
+
+//Binary tournaments with the crowded comparison operator, returns n indices
+pub fn crowded_tournament(ranks: &[Rank], n: usize) -> Vec<usize> {
+    let mut rng = rand::thread_rng();
+    let indices = (0..ranks.len()).collect::<Vec<_>>();
+    (0..n)
+        .map(|_| {
+            let a = *indices.choose(&mut rng).expect("empty population");
+            let b = *indices.choose(&mut rng).unwrap();
+            if ranks[b].compare(&ranks[a]) == Ordering::Greater {
+                b
+            } else {
+                a
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    let objectives = vec![
+        vec![1., 5.],
+        vec![2., 4.],
+        vec![3., 1.],
+        vec![1., 4.], //Dominated by the first two
+        vec![0., 0.], //Dominated by everything
+    ];
+    assert!(dominates(&objectives[0], &objectives[3]));
+    assert!(!dominates(&objectives[0], &objectives[1]));
+    assert_eq!(
+        non_dominated_sort(&objectives),
+        vec![vec![0, 1, 2], vec![3], vec![4]]
+    );
+
+    let crowding = crowding_distance(&objectives, &[0, 1, 2]);
+    assert!(crowding[0].is_infinite() && crowding[2].is_infinite());
+    assert!((crowding[1] - 2.).abs() < 1e-9); //(3 - 1) / 2 + (5 - 1) / 4
+
+    assert_eq!(select_survivors(&objectives, 4), vec![0, 1, 2, 3]);
+    let ranks = rank(&objectives);
+    assert_eq!(ranks[4].front, 2);
+    assert_eq!(ranks[0].compare(&ranks[3]), Ordering::Greater);
+    assert_eq!(crowded_tournament(&ranks, 10).len(), 10);
+
+    //Multi-objective mode of the simulator: the half-wheel design gets the dominated objectives of above,
+    //and air has none, so it doesn't take part
+    use crate::{
+        fitness::GlobalFitnessMap,
+        genetics_simulator::{EvolutionParams, GeneticsSimulator},
+    };
+    let half = Vehicle::from(
+        (0..48)
+            .map(|i| [Block::Wheel, Block::Air][i / 24])
+            .collect(),
+    );
+    let designs = [Block::Wheel, Block::Panel, Block::Air]
+        .iter()
+        .map(|b| Vehicle::new_fill_with(*b))
+        .chain(std::iter::once(half))
+        .collect::<Vec<_>>();
+    let map: GlobalFitnessMap = std::sync::Arc::new(dashmap::DashMap::new());
+    for design in &designs {
+        map.entry(design.clone()).or_default().push(0, 0, 0);
+    }
+    let params = EvolutionParams {
+        multi_objective: true,
+        ..EvolutionParams::default()
+    };
+    let mut sim = GeneticsSimulator::from_population(designs.clone());
+    sim.fill_in_fitness(&map, &params);
+    sim.fill_in_objectives(&map, |genome, _| {
+        let i = designs.iter().position(|d| d == genome).unwrap();
+        Some(objectives[i].clone()).filter(|_| i != 2) //Air has no objectives
+    });
+    sim.step(&params);
+    assert_eq!(
+        sim.get_pareto_fronts(),
+        &vec![vec![vec![1., 5.], vec![2., 4.]], vec![vec![1., 4.]]]
+    );
+    assert_eq!(sim.get_population().len(), 4);
+}
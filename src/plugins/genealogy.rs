@@ -0,0 +1,137 @@
+use crate::{
+    genealogy::Genealogy,
+    genetics_simulator::GeneticsSimulator,
+    vehicle::{Block, Vehicle, VEHICLE_SHAPE},
+};
+use bevy::prelude::*;
+use bevy_egui::{
+    egui::{self, CollapsingHeader, Pos2, ScrollArea, Ui},
+    EguiContext,
+};
+use std::convert::TryFrom;
+
+use log::{debug, error, info, trace, warn}; //IMPORTANT or you won't get any output during tests!
+
+const MAX_DEPTH: usize = 12; //Generations of ancestors that can be expanded
+
+//Shows the ancestors of a vehicle, with what crossover and mutation did to get from the parents to the child
+pub struct GenealogyPlugin;
+
+impl Plugin for GenealogyPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<FamilyTreeGui>()
+            .add_system(make_family_tree_gui.system());
+    }
+}
+
+#[derive(Default)]
+pub struct FamilyTreeGui {
+    pub selected: Option<usize>, //ID of the vehicle whose ancestors are shown, None for the champion
+}
+
+fn make_family_tree_gui(
+    egui_context: ResMut<EguiContext>,
+    sim: Res<GeneticsSimulator>,
+    mut gui: ResMut<FamilyTreeGui>,
+) {
+    egui::Window::new("Family Tree")
+        .default_pos(Pos2::new(800., 20.))
+        .show(egui_context.ctx(), |ui| {
+            let genealogy = sim.genealogy();
+            let selected = gui.selected.and_then(|id| genealogy.get(id));
+            let root = match selected.or_else(|| genealogy.champion()) {
+                Some(root) => root.id,
+                None => {
+                    ui.label("Nothing evaluated yet");
+                    return;
+                }
+            };
+
+            ui.horizontal(|ui| {
+                ui.label(format!(
+                    "{} vehicles so far, showing the ancestors of #{}",
+                    genealogy.len(),
+                    root
+                ));
+                if selected.is_some() && ui.button("Show the champion").clicked() {
+                    gui.selected = None;
+                }
+            });
+
+            ScrollArea::from_max_height(500.).show(ui, |ui| {
+                show_ancestors(ui, genealogy, root, "", 0);
+            });
+        });
+}
+
+//One collapsible entry per ancestor, with its parents inside. The same ancestor can show up in several branches,
+//so the path from the root makes the egui IDs unique.
+fn show_ancestors(
+    ui: &mut Ui,
+    genealogy: &Genealogy<Vehicle>,
+    id: usize,
+    path: &str,
+    depth: usize,
+) {
+    let individual = match genealogy.get(id) {
+        Some(individual) => individual,
+        None => return,
+    };
+    let path = format!("{}/{}", path, id);
+
+    let fitness = individual
+        .fitness
+        .map_or("?".to_owned(), |fitness| fitness.to_string());
+    let origin = match individual.parents {
+        None => "no parents".to_owned(),
+        Some(_) => format!("{} mutations", individual.mutations.len()),
+    };
+    let title = format!(
+        "#{} fitness {} (generation {}), {}",
+        id,
+        fitness,
+        individual.generation + 1,
+        origin
+    );
+
+    CollapsingHeader::new(title)
+        .id_source(&path)
+        .default_open(depth == 0)
+        .show(ui, |ui| {
+            ui.monospace(format!("{}", individual.genome));
+
+            if let (Some([a, b]), Some(point)) = (individual.parents, individual.crossover_point) {
+                ui.label(format!(
+                    "Columns 1-{} from #{}, the rest from #{}",
+                    point, a, b
+                ));
+            }
+            for mutation in &individual.mutations {
+                let block = |gene: u32| {
+                    Block::try_from(gene as u8).map_or("?".to_owned(), |block| block.to_string())
+                };
+                ui.label(format!(
+                    "Mutation in row {}, column {}: {} → {}",
+                    mutation.gene / VEHICLE_SHAPE.1 + 1,
+                    mutation.gene % VEHICLE_SHAPE.1 + 1,
+                    block(mutation.from),
+                    block(mutation.to)
+                ));
+            }
+
+            match individual.parents {
+                Some(_) if depth + 1 >= MAX_DEPTH => {
+                    ui.label("…");
+                }
+                Some([a, b]) if a == b => {
+                    ui.label("Both parents are the same:");
+                    show_ancestors(ui, genealogy, a, &path, depth + 1);
+                }
+                Some([a, b]) => {
+                    show_ancestors(ui, genealogy, a, &path, depth + 1);
+                    show_ancestors(ui, genealogy, b, &path, depth + 1);
+                }
+                None => {}
+            }
+        });
+}
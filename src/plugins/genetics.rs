@@ -1,10 +1,28 @@
 use crate::{
-    genetics_simulator::{GenerationalStatistics, GeneticsSimulator},
-    plugins::vehicle_manager::{BlockComponent, SpawnTimerState, VehicleIDs},
-    vehicle::Vehicle,
+    fitness::{
+        fitness_from_block_positions, mean_block_position, CachePolicy, FitnessRecord,
+        FitnessStatistic, GlobalFitnessMap,
+    },
+    genetics_simulator::{EvolutionParams, GenerationalStatistics, GeneticsSimulator, Pop},
+    hall_of_fame::HallOfFame,
+    islands::IslandParams,
+    map_elites::{EliteArchive, MapElitesParams},
+    niching::Niching,
+    novelty::{NoveltyParams, Objective},
+    nsga2::{self, Objectives},
+    plugins::{
+        simulation_clock::PlaybackState,
+        terrain_mesh::Tracks,
+        vehicle_manager::{BlockComponent, SpawnTimerState, VehicleIDs},
+    },
+    vehicle::{Block, Vehicle, VEHICLE_SHAPE},
     vehicle_states::VehicleID,
 };
 use crate::{
+    speciation::{SpeciationParams, SpeciesStatistics},
+    steady_state::SteadyStateParams,
+    track::{generator::TrackGeneratorParams, TrackMetadata, TrackSet, TrackSource},
+    track_evolution::{self, TrackEvolver, TrackGenerationStatistics},
     utility::invlerp,
     vehicle_states::{VehicleStates, VehicleStatus},
 };
@@ -15,6 +33,7 @@ use bevy_egui::{
 };
 use bevy_inspector_egui::Inspectable;
 use bevy_inspector_egui::InspectorPlugin;
+use bevy_rapier2d::prelude::*;
 use dashmap::DashMap;
 use egui::{Color32, Label};
 use std::sync::Arc;
@@ -30,6 +49,7 @@ impl Plugin for GeneticsPlugin {
         app.add_system(calculate_fitness_of_current_vehicles.system());
         app.add_system(make_gui.system());
         app.add_system(evolve_if_finished.system());
+        app.add_system(evolve_steady_state.system());
         app.add_plugin(InspectorPlugin::<SimulationParams>::new());
     }
 }
@@ -46,14 +66,111 @@ pub struct SimulationParams {
     pub mutation_amount: u32, //NOTE: keep mutation amount very low (<3)
 
     #[inspectable(min = 4., max = 60.)]
-    pub max_generation_duration: f32, //Seconds
+    pub max_generation_duration: f32, //Simulated seconds
+
+    #[inspectable(min = 0.25, max = 16.)]
+    pub simulation_speed: f32, //1 = real time
+
+    pub as_fast_as_possible: bool, //Ignores simulation_speed
 
     #[inspectable(min = 0., max = 0.9)]
     pub unhovered_alpha: f32,
 
+    pub track_source: TrackSource,
+    pub track_generator: TrackGeneratorParams,
+    pub track_file: String, //.json, .png or .svg, only used if track_source is File
+    pub track_set: TrackSet,
+    pub curriculum: CurriculumParams,
+    pub coevolution: CoevolutionParams,
+
     pub camera_lock: bool,
     pub place_only_best_vehicle: bool,
     pub show_green_screen: bool,
+
+    #[inspectable(min = 1, max = 8)]
+    pub evaluations_per_vehicle: u32, //Evaluation is noisy, so simulating a vehicle more than once gives a better estimate
+
+    #[inspectable(min = 0., max = 300.)]
+    pub spawn_jitter: f32, //Pixels, repeated evaluations get spawned this far left/right of the spawn point
+
+    pub selection_statistic: FitnessStatistic,
+
+    pub cache_policy: CachePolicy,
+
+    #[inspectable(min = 1, max = 50)]
+    pub cache_expiry_generations: u32, //Only used by CachePolicy::ExpireAfterGenerations
+
+    pub evaluation_backend: EvaluationBackend,
+
+    #[inspectable(min = 1, max = 64)]
+    pub worker_threads: u32, //Only used by the parallel backend
+
+    #[inspectable(min = 2, max = 100)]
+    pub stagnation_warning: u32, //Warn after this many generations without a new best fitness
+
+    pub niching: Niching,
+
+    #[inspectable(min = 1., max = 48.)]
+    pub sharing_radius: f32, //Blocks, vehicles that differ in fewer blocks share their fitness
+
+    pub speciation: SpeciationParams, //Only used by Niching::Speciation
+
+    pub islands: IslandParams,
+
+    pub novelty: NoveltyParams, //Novelty search, rewards behaving differently instead of (or besides) driving far
+
+    pub map_elites: MapElitesParams,
+
+    pub multi_objective: bool, //NSGA-II on distance, number of blocks and integrity instead of the fitness
+
+    pub steady_state: SteadyStateParams, //Breed whenever vehicles finish, instead of once per generation
+
+    #[inspectable(min = 1, max = 100)]
+    pub hall_of_fame_size: u32, //Best vehicles of all generations that are kept
+}
+
+#[derive(Inspectable, PartialEq, Clone, Copy, Debug)]
+pub enum EvaluationBackend {
+    Rendered, //Simulate the vehicles in the bevy world, so you can watch them
+    Parallel, //Simulate the vehicles headless on worker threads (native only)
+}
+
+impl SimulationParams {
+    pub fn uses_parallel_backend(&self) -> bool {
+        self.evaluation_backend == EvaluationBackend::Parallel && cfg!(not(target_arch = "wasm32"))
+    }
+
+    //Number of tracks every vehicle gets evaluated on
+    pub fn track_count(&self) -> usize {
+        if self.coevolution.enabled {
+            track_evolution::population_size(self.coevolution.track_population as usize)
+        } else {
+            self.track_set.track_count(self.track_source)
+        }
+    }
+
+    pub fn score(&self, record: &FitnessRecord) -> i64 {
+        record.score(self.selection_statistic, &self.track_set)
+    }
+
+    pub fn evolution_params(&self) -> EvolutionParams {
+        EvolutionParams {
+            tournament_k: self.tournament_k as usize,
+            mutation_amount: self.mutation_amount as usize,
+            selection_statistic: self.selection_statistic,
+            cache_policy: self.cache_policy,
+            cache_expiry_generations: self.cache_expiry_generations as usize,
+            track_count: self.track_count(),
+            track_set: self.track_set.clone(),
+            niching: self.niching,
+            sharing_radius: self.sharing_radius as f64,
+            speciation: self.speciation.clone(),
+            islands: self.islands.clone(),
+            novelty: self.novelty.clone(),
+            multi_objective: self.multi_objective,
+            steady_state: self.steady_state.clone(),
+        }
+    }
 }
 
 impl Default for SimulationParams {
@@ -63,15 +180,99 @@ impl Default for SimulationParams {
             tournament_k: 10,
             mutation_amount: 1,
             max_generation_duration: 24.0,
+            simulation_speed: 1.0,
+            as_fast_as_possible: false,
             unhovered_alpha: 0.1,
+            track_source: TrackSource::TerrainRoad,
+            track_generator: TrackGeneratorParams::default(),
+            track_file: "assets/tracks/example.json".to_owned(),
+            track_set: TrackSet::default(),
+            curriculum: CurriculumParams::default(),
+            coevolution: CoevolutionParams::default(),
             camera_lock: true,
             place_only_best_vehicle: false,
             show_green_screen: false,
+            evaluations_per_vehicle: 1,
+            spawn_jitter: 0.,
+            selection_statistic: FitnessStatistic::Mean,
+            cache_policy: CachePolicy::ReevaluateAndAverage,
+            cache_expiry_generations: 5,
+            evaluation_backend: EvaluationBackend::Rendered,
+            worker_threads: std::thread::available_parallelism()
+                .map(|n| n.get() as u32)
+                .unwrap_or(4),
+            stagnation_warning: 15,
+            niching: Niching::Off,
+            sharing_radius: 6.,
+            speciation: SpeciationParams::default(),
+            islands: IslandParams::default(),
+            novelty: NoveltyParams::default(),
+            map_elites: MapElitesParams::default(),
+            multi_objective: false,
+            steady_state: SteadyStateParams::default(),
+            hall_of_fame_size: 10,
+        }
+    }
+}
+
+//Instead of the whole track at once, only the first part counts for fitness at first.
+//Once enough of the population reaches the end of that part, the next one is unlocked.
+#[derive(Inspectable, Clone, Debug)]
+pub struct CurriculumParams {
+    pub enabled: bool,
+
+    #[inspectable(min = 2, max = 10)]
+    pub stages: u32, //The track is split into this many equal parts
+
+    #[inspectable(min = 0.05, max = 1.)]
+    pub promotion_fraction: f32, //Fraction of the population that has to reach the goal to unlock the next stage
+}
+
+impl Default for CurriculumParams {
+    fn default() -> Self {
+        CurriculumParams {
+            enabled: false,
+            stages: 4,
+            promotion_fraction: 0.5,
         }
     }
 }
 
-pub type GlobalFitnessMap = Arc<DashMap<Vehicle, i64>>;
+impl CurriculumParams {
+    //Fitness needed to complete the given stage, the last stage ends at the finish line
+    pub fn goal(&self, stage: usize, finish_distance: i64) -> i64 {
+        let stages = self.stages.max(1) as usize;
+        let stage = stage.min(stages - 1) + 1;
+        finish_distance * stage as i64 / stages as i64
+    }
+
+    pub fn is_last_stage(&self, stage: usize) -> bool {
+        stage + 1 >= self.stages as usize
+    }
+}
+
+//Evolves a population of procedural tracks alongside the vehicles, see TrackEvolver.
+//Replaces the track set while enabled, the first population consists of variants of track_generator.
+#[derive(Inspectable, Clone, Debug)]
+pub struct CoevolutionParams {
+    pub enabled: bool,
+
+    #[inspectable(min = 2, max = 8)]
+    pub track_population: u32, //Takes effect in the next generation, rounded up to an even number
+
+    #[inspectable(min = 1, max = 5)]
+    pub track_mutations: u32, //Mutations applied to every new track
+}
+
+impl Default for CoevolutionParams {
+    fn default() -> Self {
+        CoevolutionParams {
+            enabled: false,
+            track_population: 4,
+            track_mutations: 2,
+        }
+    }
+}
 
 #[derive(Default)]
 pub struct GeneticsGuiState {
@@ -82,17 +283,14 @@ fn make_gui(
     egui_context: ResMut<EguiContext>,
     vehicle_states: Res<VehicleStates>,
     sim: NonSend<GeneticsSimulator>,
+    fitness_map: Res<GlobalFitnessMap>,
     spawn_state: Res<SpawnTimerState>,
     mut gui_state: ResMut<GeneticsGuiState>,
+    mut playback: ResMut<PlaybackState>,
+    params: Res<SimulationParams>,
+    tracks: Res<Tracks>,
+    track_evolver: Res<TrackEvolver>,
 ) {
-    let gradient = colorous::WARM;
-
-    let fitness_to_color = |fitness: f64| {
-        let t = invlerp(0., 9000., fitness as f32).clamp(0., 1.);
-        let col = gradient.eval_continuous(t as f64);
-        Color32::from_rgb(col.r, col.g, col.b)
-    };
-
     gui_state.hovered_id = None;
 
     let generations_changed = true; //TODO only scroll when a new entry appears in the list, not every frame
@@ -110,7 +308,11 @@ fn make_gui(
                 ui.vertical(|ui| {
                     for (i, stat) in stats.iter().enumerate() {
                         ui.horizontal_wrapped(|ui| {
-                            ui.label(format!("Generation {:#3}. Avg=", i + 1));
+                            if params.steady_state.enabled {
+                                ui.label(format!("Evaluations {:#5}. Avg=", stat.evaluations));
+                            } else {
+                                ui.label(format!("Generation {:#3}. Avg=", i + 1));
+                            }
                             ui.colored_label(
                                 fitness_to_color(stat.avg_fitness),
                                 format!("{:#5}", stat.avg_fitness.round()),
@@ -120,6 +322,15 @@ fn make_gui(
                                 fitness_to_color(stat.max_fitness),
                                 format!("{:#5}", stat.max_fitness.round()),
                             );
+                            ui.label(format!(" Unique={:#2}", stat.diversity.unique_genomes));
+                            if let Some(goal) = stat.curriculum_goal {
+                                ui.label(format!(
+                                    " Stage {} (goal {}, {:.0}% reached)",
+                                    stat.curriculum_stage + 1,
+                                    goal,
+                                    stat.reached_goal * 100.
+                                ));
+                            }
                         });
                     }
                 });
@@ -129,7 +340,115 @@ fn make_gui(
                 }
             });
             ui.separator();
-            make_fitness_plot(ui, stats);
+            if params.multi_objective {
+                make_pareto_plot(ui, sim.get_pareto_fronts());
+            } else {
+                make_fitness_plot(ui, stats);
+            }
+            make_diversity_plot(ui, stats);
+
+            if let Some(stat) = stats.last() {
+                ui.label(format!(
+                    "Diversity: {}/{} unique, mean distance {:.1} blocks, entropy {:.2} bits",
+                    stat.diversity.unique_genomes,
+                    stat.diversity.population_size,
+                    stat.diversity.mean_distance,
+                    stat.diversity.entropy
+                ));
+            }
+            if params.novelty.objective != Objective::Fitness {
+                ui.label(format!(
+                    "Novelty archive: {} behaviors, avg novelty {:.0}",
+                    sim.novelty_archive().len(),
+                    stats.last().and_then(|s| s.avg_novelty).unwrap_or(0.)
+                ));
+            }
+            let stagnant = sim.stagnant_generations();
+            if stagnant >= params.stagnation_warning as usize {
+                ui.colored_label(
+                    Color32::YELLOW,
+                    format!("⚠ No new best fitness for {} generations", stagnant),
+                );
+            }
+            ui.separator();
+
+            if params.coevolution.enabled {
+                ui.collapsing("Track population", |ui| {
+                    for (i, (generator, score)) in track_evolver
+                        .population()
+                        .iter()
+                        .zip(track_evolver.scores())
+                        .enumerate()
+                    {
+                        let result = match score {
+                            Some(score) if score.finishable => {
+                                format!("difficulty {:.2}", score.difficulty)
+                            }
+                            Some(_) => "not finishable".to_owned(),
+                            None => "not evaluated yet".to_owned(),
+                        };
+                        ui.label(format!("T{} {}: {}", i + 1, generator.summary(), result));
+                    }
+                    make_track_plot(ui, track_evolver.get_generational_statistics());
+                });
+                ui.separator();
+            }
+
+            if let Some(islands) = sim.get_island_statistics().last() {
+                if params.islands.enabled {
+                    ui.collapsing("Islands", |ui| {
+                        for (i, (stats, settings)) in
+                            islands.iter().zip(&params.islands.islands).enumerate()
+                        {
+                            ui.label(format!(
+                                "I{} (k={}, mutations={}): {} vehicles, avg {:.0}, max {:.0}",
+                                i + 1,
+                                settings.tournament_k,
+                                settings.mutation_amount,
+                                stats.size,
+                                stats.avg_fitness,
+                                stats.max_fitness
+                            ));
+                        }
+                    });
+                    ui.separator();
+                }
+            }
+
+            if params.niching == Niching::Speciation {
+                ui.collapsing("Species", |ui| {
+                    for species in sim.speciation().species() {
+                        ui.colored_label(
+                            species_color(species.id),
+                            format!(
+                                "S{} since generation {}: {} vehicles, best ever {}",
+                                species.id,
+                                species.created + 1,
+                                species.members.len(),
+                                species.best_fitness.unwrap_or(0)
+                            ),
+                        );
+                    }
+                    make_species_plot(ui, sim.speciation().get_generational_statistics());
+                });
+                ui.separator();
+            }
+
+            if let Some(cache_stats) = sim.get_cache_statistics().last() {
+                let (hits, lookups) = sim
+                    .get_cache_statistics()
+                    .iter()
+                    .fold((0, 0), |(h, l), x| (h + x.hits, l + x.lookups));
+                ui.label(format!(
+                    "Cache hits: {}/{} this generation, {:.0}% overall",
+                    cache_stats.hits,
+                    cache_stats.lookups,
+                    100. * hits as f64 / lookups.max(1) as f64
+                ));
+                ui.separator();
+            }
+
+            make_playback_controls(ui, &mut playback, sim.generation());
             ui.separator();
 
             let progress_bar_len = 18;
@@ -154,7 +473,30 @@ fn make_gui(
             ui.label("Population:");
 
             for (i, state) in vehicle_states.get_vehicle_states().iter().enumerate() {
-                let mut l = Label::new(format!("{:02}. {}", i + 1, state));
+                let mut prefix = format!("{:02}.", i + 1);
+                if params.track_count() > 1 {
+                    prefix += &format!(" T{}", state.track + 1);
+                }
+                if state.evaluation > 0 {
+                    prefix += &format!(" #{}", state.evaluation + 1);
+                }
+                if let Some(island) = state.island {
+                    prefix += &format!(" I{}", island + 1);
+                }
+                if state.requested {
+                    prefix += " ★";
+                }
+                if params.niching == Niching::Speciation {
+                    //Children are classified by the species of the last generation, so it's a guess until they're evaluated
+                    match sim
+                        .speciation()
+                        .classify(&state.vehicle, &params.speciation)
+                    {
+                        Some(id) => prefix += &format!(" S{}", id),
+                        None => prefix += " S?",
+                    }
+                }
+                let mut l = Label::new(format!("{} {}", prefix, state));
 
                 if state.status != VehicleStatus::Pending {
                     l = l.text_color(fitness_to_color(state.fitness as f64));
@@ -167,31 +509,119 @@ fn make_gui(
                     }
 
                     ui.monospace(format!("{}", state.vehicle));
+
+                    if let Some(record) = fitness_map.get(&state.vehicle) {
+                        ui.label(format!(
+                            "Evaluated {}x: mean={:.0} min={} variance={:.0}",
+                            record.len(),
+                            record.mean(),
+                            record.min(),
+                            record.variance()
+                        ));
+                        if let Some(descriptor) = record.descriptor() {
+                            //Distance, height, ground contact and falling apart, weighted like novelty search does
+                            ui.label(format!("Behavior: {:.0?}", descriptor));
+                        }
+
+                        if params.track_count() > 1 {
+                            for track in 0..params.track_count() {
+                                let on_track = record.on_track(track);
+                                if on_track.is_empty() {
+                                    continue;
+                                }
+                                ui.label(format!(
+                                    "T{} {}: {}",
+                                    track + 1,
+                                    tracks.name(track),
+                                    on_track.statistic(params.selection_statistic)
+                                ));
+                            }
+                            ui.label(format!(
+                                "{:?} over all tracks: {}",
+                                params.track_set.aggregation,
+                                params.score(&record)
+                            ));
+                        }
+                    }
                 });
             }
         });
 }
 
+fn make_playback_controls(ui: &mut Ui, playback: &mut PlaybackState, generation: usize) {
+    ui.horizontal(|ui| {
+        if playback.paused {
+            if ui.button("▶ Resume").clicked() {
+                playback.resume();
+            }
+        } else if ui.button("⏸ Pause").clicked() {
+            playback.pause();
+        }
+
+        if ui
+            .add(egui::Button::new("Step tick").enabled(playback.paused))
+            .clicked()
+        {
+            playback.pending_ticks += 1;
+        }
+
+        if ui.button("Finish batch").clicked() {
+            playback.resume();
+            playback.pause_after_batch = true;
+        }
+    });
+
+    ui.horizontal(|ui| {
+        if ui.button("Run generations:").clicked() {
+            playback.resume();
+            playback.pause_at_generation = Some(generation + playback.generations_to_run as usize);
+        }
+        ui.add(egui::Slider::new(&mut playback.generations_to_run, 1..=100));
+    });
+
+    if let Some(target) = playback.pause_at_generation {
+        ui.label(format!("Pausing at generation {}", target + 1));
+    }
+}
+
 fn make_fitness_plot(ui: &mut Ui, gen: &[GenerationalStatistics]) {
     use egui::plot::{Curve, Plot, Value};
 
-    let avg_iter = gen
-        .iter()
-        .enumerate()
-        .map(|(i, stats)| Value::new(i as f64, stats.avg_fitness));
-    let avg_curve = Curve::from_values_iter(avg_iter).name("Average fitness");
+    let curve = |name: &str, value: fn(&GenerationalStatistics) -> f64| {
+        let values = gen
+            .iter()
+            .enumerate()
+            .map(|(i, stats)| Value::new(i as f64, value(stats)));
+        Curve::from_values_iter(values).name(name)
+    };
 
-    let max_iter = gen
-        .iter()
-        .enumerate()
-        .map(|(i, stats)| Value::new(i as f64, stats.max_fitness));
-    let max_curve = Curve::from_values_iter(max_iter).name("Max fitness");
+    let mut plot = Plot::new("Avg Fitness Plot")
+        .curve(curve("Average fitness", |s| s.avg_fitness))
+        .curve(curve("Max fitness", |s| s.max_fitness))
+        .curve(curve("Min fitness", |s| s.min_fitness))
+        .curve(curve("Median fitness", |s| s.median_fitness))
+        .curve(curve("Fitness std. dev.", |s| s.stddev_fitness));
+
+    //Jumps up whenever a new curriculum stage got unlocked
+    if gen.iter().any(|stats| stats.curriculum_goal.is_some()) {
+        let goal_iter = gen.iter().enumerate().filter_map(|(i, stats)| {
+            stats
+                .curriculum_goal
+                .map(|goal| Value::new(i as f64, goal as f64))
+        });
+        plot = plot.curve(Curve::from_values_iter(goal_iter).name("Curriculum goal"));
+    }
+    if gen.iter().any(|stats| stats.avg_novelty.is_some()) {
+        let novelty_iter = gen.iter().enumerate().filter_map(|(i, stats)| {
+            stats
+                .avg_novelty
+                .map(|novelty| Value::new(i as f64, novelty))
+        });
+        plot = plot.curve(Curve::from_values_iter(novelty_iter).name("Average novelty"));
+    }
 
     ui.add(
-        Plot::new("Avg Fitness Plot")
-            .curve(avg_curve)
-            .curve(max_curve)
-            .include_x(0)
+        plot.include_x(0)
             .include_x(60)
             .include_y(0)
             .include_y(14900)
@@ -200,58 +630,254 @@ fn make_fitness_plot(ui: &mut Ui, gen: &[GenerationalStatistics]) {
     );
 }
 
+fn make_diversity_plot(ui: &mut Ui, gen: &[GenerationalStatistics]) {
+    use egui::plot::{Curve, Plot, Value};
+
+    let curve = |name: &str, value: fn(&GenerationalStatistics) -> f64| {
+        let values = gen
+            .iter()
+            .enumerate()
+            .map(|(i, stats)| Value::new(i as f64, value(stats)));
+        Curve::from_values_iter(values).name(name)
+    };
+
+    //Shares, so they fit on the same axis. Entropy of 3 block types is at most 1.58 bits.
+    ui.add(
+        Plot::new("Diversity Plot")
+            .curve(curve("Unique genomes", |s| s.diversity.unique_share()))
+            .curve(curve("Mean distance", |s| s.diversity.relative_distance()))
+            .curve(curve("Block entropy (bits)", |s| s.diversity.entropy))
+            .include_x(0)
+            .include_x(60)
+            .include_y(0)
+            .include_y(1)
+            .view_aspect(6.0)
+            .show_legend(true),
+    );
+}
+
+//Distance against number of blocks of the last multi-objective generation, one color per non-dominated front.
+//Every vehicle is drawn as a small cross, the vehicles of a front are connected from left to right.
+fn make_pareto_plot(ui: &mut Ui, fronts: &[Vec<Objectives>]) {
+    use egui::plot::{Curve, Plot, Value};
+    const PLOTTED_FRONTS: usize = 5;
+
+    let point = |objectives: &Objectives| Value::new(objectives[0], -objectives[1]);
+    let mut plot = Plot::new("Pareto Front Plot");
+    for (f, front) in fronts.iter().enumerate().take(PLOTTED_FRONTS) {
+        let color = species_color(f); //Any set of distinct colors will do
+
+        let mut sorted = front.iter().map(point).collect::<Vec<_>>();
+        sorted.sort_by(|a, b| a.x.partial_cmp(&b.x).unwrap());
+        for value in &sorted {
+            let horizontal = [(-100., 0.), (100., 0.)];
+            let vertical = [(0., -0.5), (0., 0.5)];
+            for arm in [horizontal, vertical] {
+                let ends = arm
+                    .iter()
+                    .map(|(dx, dy)| Value::new(value.x + dx, value.y + dy));
+                plot = plot.curve(Curve::from_values_iter(ends).color(color));
+            }
+        }
+
+        let name = if f == 0 {
+            "Pareto front".to_owned()
+        } else {
+            format!("Front {}", f + 1)
+        };
+        plot = plot.curve(Curve::from_values(sorted).color(color).name(name));
+    }
+
+    ui.label(format!(
+        "↑ {} (fewer is better), → {}",
+        nsga2::VEHICLE_OBJECTIVES[1],
+        nsga2::VEHICLE_OBJECTIVES[0]
+    ));
+    if let Some(front) = fronts.first() {
+        let integrity = front.iter().map(|o| o[2]).collect::<Vec<_>>();
+        ui.label(format!(
+            "Pareto front: {} vehicles, {}: {:.0}% to {:.0}%",
+            front.len(),
+            nsga2::VEHICLE_OBJECTIVES[2],
+            integrity.iter().cloned().fold(f64::INFINITY, f64::min) * 100.,
+            integrity.iter().cloned().fold(f64::NEG_INFINITY, f64::max) * 100.
+        ));
+    }
+    ui.add(
+        plot.include_x(0)
+            .include_y(0)
+            .include_y((VEHICLE_SHAPE.0 * VEHICLE_SHAPE.1) as f64)
+            .view_aspect(4.0)
+            .show_legend(true),
+    );
+}
+
+fn make_track_plot(ui: &mut Ui, gen: &[TrackGenerationStatistics]) {
+    use egui::plot::{Curve, Plot, Value};
+
+    let curve = |name: &str, value: fn(&TrackGenerationStatistics) -> f64| {
+        let values = gen
+            .iter()
+            .enumerate()
+            .map(|(i, stats)| Value::new(i as f64, value(stats)));
+        Curve::from_values_iter(values).name(name)
+    };
+
+    ui.add(
+        Plot::new("Track Difficulty Plot")
+            .curve(curve("Average difficulty", |s| s.avg_difficulty))
+            .curve(curve("Max finishable difficulty", |s| s.max_difficulty))
+            .curve(curve("Finishable tracks", |s| s.finishable_share))
+            .include_x(0)
+            .include_x(60)
+            .include_y(0)
+            .include_y(1)
+            .view_aspect(4.0)
+            .show_legend(true),
+    );
+}
+
+pub fn fitness_to_color(fitness: f64) -> Color32 {
+    let t = invlerp(0., 9000., fitness as f32).clamp(0., 1.);
+    let col = colorous::WARM.eval_continuous(t as f64);
+    Color32::from_rgb(col.r, col.g, col.b)
+}
+
+//Same species keep the same color in the list and in the plot
+fn species_color(id: usize) -> Color32 {
+    let col = colorous::CATEGORY10[id % colorous::CATEGORY10.len()];
+    Color32::from_rgb(col.r, col.g, col.b)
+}
+
+//Max fitness of every species over time, a curve ends when its species dies out
+fn make_species_plot(ui: &mut Ui, gen: &[Vec<SpeciesStatistics>]) {
+    use egui::plot::{Curve, Plot, Value};
+
+    let mut ids = gen.iter().flatten().map(|s| s.id).collect::<Vec<_>>();
+    ids.sort_unstable();
+    ids.dedup();
+
+    let mut plot = Plot::new("Species Plot");
+    for id in ids {
+        let values = gen.iter().enumerate().filter_map(|(i, species)| {
+            species
+                .iter()
+                .find(|s| s.id == id)
+                .map(|s| Value::new(i as f64, s.max_fitness))
+        });
+        plot = plot.curve(
+            Curve::from_values_iter(values)
+                .color(species_color(id))
+                .name(format!("S{}", id)),
+        );
+    }
+
+    ui.add(
+        plot.include_x(0)
+            .include_x(60)
+            .include_y(0)
+            .include_y(14900)
+            .view_aspect(4.0),
+    );
+}
+
 fn calculate_fitness_of_current_vehicles(
-    query: Query<(&Transform, &BlockComponent)>,
+    query: Query<(Entity, &Transform, &BlockComponent)>,
+    narrow_phase: Res<NarrowPhase>,
     mut vehicle_states: ResMut<VehicleStates>,
     vehicle_ids: Res<VehicleIDs>,
+    params: Res<SimulationParams>,
+    track: Res<TrackMetadata>,
 ) {
-    let max_diff = 1000; //How far min/max can be apart in X coordinates before we start punishment
+    if params.uses_parallel_backend() {
+        return; //The parallel evaluator reports fitness by itself
+    }
 
     if !vehicle_ids.is_empty() {
         for id in vehicle_ids.iter() {
-            let blocks = query
+            //Measured from the spawn point, and don't reward a lucky spawn position
+            let spawn_offset = track.spawn_point[0] + vehicle_states.get_spawn_offset(*id);
+
+            let vehicle_blocks = query
+                .iter()
+                .filter(|(_, _, block_comp)| block_comp.belongs_to == *id)
+                .collect::<Vec<_>>();
+            let blocks = vehicle_blocks
                 .iter()
-                .filter(|(_, block_comp)| block_comp.belongs_to == *id)
-                .map(|(transform, _)| transform.translation.x.round() as i64)
+                .map(|(_, transform, _)| (transform.translation.x - spawn_offset).round() as i64)
                 .collect::<Vec<_>>();
             //Need to collect here since you can't use the same iterator twice
 
-            let len = blocks.len();
+            let (fitness, fell_apart) = fitness_from_block_positions(&blocks);
 
-            let mut fitness = if len == 0 {
-                0.0
-            } else {
-                let sum = blocks.iter().sum::<i64>();
-                sum as f64 / (len as f64)
-            };
-
-            let mut fitness_punishment_multiplier = 1.0;
-
-            let mut fell_apart = false;
-            let (min, max) = (blocks.iter().min(), blocks.iter().max());
-            if let (Some(min), Some(max)) = (min, max) {
-                let diff = (max - min).abs();
-                if diff > max_diff {
-                    fitness_punishment_multiplier *= 0.1;
-                    fell_apart = true;
-                }
-            }
+            vehicle_states.set_fitness(*id, fitness, fell_apart, &track);
 
-            fitness *= fitness_punishment_multiplier;
-
-            let fitness = fitness.round() as i64;
-
-            vehicle_states.set_fitness(*id, fitness, fell_apart);
+            //Recorded for novelty search, even if it's off, so the archive can be used later
+            let height = vehicle_blocks
+                .iter()
+                .map(|(_, transform, _)| transform.translation.y - track.spawn_point[1])
+                .fold(f32::NEG_INFINITY, f32::max);
+            let wheels = vehicle_blocks
+                .iter()
+                .filter(|(_, _, block_comp)| block_comp.block == Block::Wheel)
+                .collect::<Vec<_>>();
+            let touching = wheels
+                .iter()
+                .filter(|(entity, _, _)| touches_ground(&narrow_phase, *entity, &query))
+                .count();
+            vehicle_states.observe_behavior(
+                *id,
+                mean_block_position(&blocks) as f32,
+                height,
+                touching,
+                wheels.len(),
+            );
         }
     } else {
         warn!("can't update fitness (no active vehicles)");
     }
 }
 
+//Whether the block touches anything that isn't part of a vehicle, like the terrain or an obstacle
+fn touches_ground(
+    narrow_phase: &NarrowPhase,
+    entity: Entity,
+    blocks: &Query<(Entity, &Transform, &BlockComponent)>,
+) -> bool {
+    let handle = entity.handle();
+    narrow_phase.contacts_with(handle).any(|pair| {
+        let other = if pair.collider1 == handle {
+            pair.collider2
+        } else {
+            pair.collider1
+        };
+        pair.has_any_active_contact && blocks.get(other.entity()).is_err()
+    })
+}
+
 fn setup_genetics(world: &mut World) {
     let (sim, population, map) = initialize_vehicle_sim();
-    let states = VehicleStates::from(population);
+    let params = world.get_resource::<SimulationParams>();
+    let (evaluations, track_count) = params.map_or((1, 1), |params| {
+        (params.evaluations_per_vehicle, params.track_count())
+    });
+    let track_evolver = params.map_or_else(
+        || TrackEvolver::new(&TrackGeneratorParams::default(), 1),
+        |params| {
+            TrackEvolver::new(
+                &params.track_generator,
+                params.coevolution.track_population as usize,
+            )
+        },
+    );
+    let islands = params.map(|params| params.islands.ranges(population.len()));
+    let population = population.into_iter().map(|v| (v, None)).collect();
+    let states = states_by_island(population, islands.flatten(), |island| {
+        let vehicles = island.into_iter().map(|(v, _)| v).collect();
+        VehicleStates::from(vehicles, evaluations, track_count)
+    });
     world.insert_resource(states);
+    world.insert_resource(track_evolver);
     world.insert_resource(sim);
     world.insert_resource(map);
 }
@@ -273,20 +899,82 @@ fn evolve_if_finished(
     mut vehicle_states: ResMut<VehicleStates>,
     map: Res<GlobalFitnessMap>,
     params: Res<SimulationParams>,
+    mut playback: ResMut<PlaybackState>,
+    track: Res<TrackMetadata>,
+    mut tracks: ResMut<Tracks>,
+    mut track_evolver: ResMut<TrackEvolver>,
+    mut archive: ResMut<EliteArchive>,
+    mut hall_of_fame: ResMut<HallOfFame>,
 ) {
-    if !vehicle_states.all_done() {
+    if params.steady_state.enabled || !vehicle_states.all_done() {
         return;
     }
 
     info!("Evolving...");
-    sim.fill_in_fitness(&map);
-    sim.step(&params);
+    let evolution_params = params.evolution_params();
+    sim.fill_in_fitness(&map, &evolution_params);
+    if params.multi_objective {
+        sim.fill_in_objectives(&map, nsga2::vehicle_objectives);
+    }
+
+    if params.coevolution.enabled {
+        //Judge the tracks by how the vehicles that were just evaluated did on them
+        let evaluated = sim.get_population_genomes();
+        for track_index in 0..track_evolver.population().len() {
+            let fitness = evaluated
+                .iter()
+                .filter_map(|vehicle| map.get(vehicle))
+                .map(|record| record.on_track(track_index))
+                .filter(|record| !record.is_empty())
+                .map(|record| record.statistic(params.selection_statistic))
+                .collect::<Vec<_>>();
+            let finish_distance = tracks
+                .get(track_index)
+                .map_or(track.finish_distance(), |t| t.metadata.finish_distance());
+            track_evolver.set_fitness(track_index, &fitness, finish_distance);
+        }
+    }
+
+    let goal = curriculum_goal(&params, &sim, &tracks, &track);
+    //Every evaluated vehicle competes for its cell of the archive and the hall of fame, with its fitness on the whole track
+    let mut new_elites = 0;
+    for (vehicle, fitness) in sim.get_population() {
+        if let Some(fitness) = fitness {
+            if archive.insert(vehicle, *fitness, sim.generation()) {
+                new_elites += 1;
+            }
+            hall_of_fame.insert(vehicle, *fitness, sim.generation());
+        }
+    }
+    info!(
+        "{} new elites, {} in total",
+        new_elites,
+        archive.elites().len()
+    );
+
+    sim.apply_curriculum(goal);
+    if params.map_elites.breed_from_archive && !archive.is_empty() {
+        //The archive replaces the simulator's own breeding
+        let pop_size = sim.get_population().len();
+        sim.step_from(&evolution_params, |genealogy, generation| {
+            archive.offspring(
+                pop_size,
+                params.mutation_amount as usize,
+                genealogy,
+                generation,
+            )
+        });
+    } else {
+        sim.step(&evolution_params);
+    }
+
+    after_generation(&mut sim, &params, &mut playback);
 
     if params.place_only_best_vehicle {
-        let best = map.iter().max_by_key(|x| *x.value()).unwrap();
+        let best = map.iter().max_by_key(|x| params.score(x.value())).unwrap();
         info!(
             "Replacing all vehicles with best vehicle with fitness {}: \n{}",
-            best.value(),
+            params.score(best.value()),
             best.key()
         );
 
@@ -294,9 +982,169 @@ fn evolve_if_finished(
         sim.overwrite_population(vec![best.key().clone(); pop_size]); //Make x copies of the best vehicle
     }
 
+    if params.coevolution.enabled {
+        let old_tracks = track_evolver.population();
+        track_evolver.step(
+            params.coevolution.track_population as usize,
+            params.coevolution.track_mutations,
+        );
+        //Fitness measured on the old tracks only says something about the tracks that are still around,
+        //so the cache keeps working for those
+        let sources = track_evolution::unchanged_tracks(&old_tracks, &track_evolver.population());
+        map.retain(|_, record| {
+            record.remap_tracks(&sources);
+            !record.is_empty()
+        });
+        tracks.clear();
+    }
+
     info!("Simulation stepped");
-    let vehicles = sim.get_population_vehicles();
 
-    //Then, reset all vehicle states
-    *vehicle_states = VehicleStates::from(vehicles);
+    //Then, reset all vehicle states. Vehicles with a cached fitness don't need to be simulated again.
+    let population = if params.place_only_best_vehicle {
+        //Never skip here, or the best vehicle would never be shown
+        sim.get_population_genomes()
+            .into_iter()
+            .map(|v| (v, None))
+            .collect()
+    } else {
+        sim.lookup_cached_fitness(&map, &evolution_params)
+    };
+    let islands = evolution_params.islands.ranges(population.len());
+    *vehicle_states = states_by_island(population, islands, |island| {
+        VehicleStates::from_cached(
+            island,
+            params.evaluations_per_vehicle,
+            params.track_count(),
+            &track,
+        )
+    });
+}
+
+//Steady-state GA: every time vehicles finish, their offspring are queued, so the track never runs empty.
+//Coevolution, island and niching settings only apply to generational evolution.
+fn evolve_steady_state(
+    mut sim: ResMut<GeneticsSimulator>,
+    mut vehicle_states: ResMut<VehicleStates>,
+    map: Res<GlobalFitnessMap>,
+    params: Res<SimulationParams>,
+    mut playback: ResMut<PlaybackState>,
+    track: Res<TrackMetadata>,
+    tracks: Res<Tracks>,
+    mut archive: ResMut<EliteArchive>,
+    mut hall_of_fame: ResMut<HallOfFame>,
+) {
+    if !params.steady_state.enabled {
+        return;
+    }
+    let finished = vehicle_states.take_finished();
+    if finished.is_empty() {
+        return;
+    }
+
+    let evolution_params = params.evolution_params();
+    for vehicle in &finished {
+        if let Some(record) = map.get(vehicle) {
+            archive.insert(vehicle, params.score(&record), sim.generation());
+            hall_of_fame.insert(vehicle, params.score(&record), sim.generation());
+        }
+    }
+
+    let goal = curriculum_goal(&params, &sim, &tracks, &track);
+    sim.apply_curriculum(goal);
+    let reports = sim.generation();
+    let offspring = sim.steady_state_step(&finished, &map, &evolution_params);
+    if sim.generation() > reports {
+        after_generation(&mut sim, &params, &mut playback);
+    }
+
+    let offspring = offspring
+        .into_iter()
+        .map(|vehicle| {
+            let fitness = sim.cached_fitness(&vehicle, &map, &evolution_params);
+            (vehicle, fitness)
+        })
+        .collect();
+    vehicle_states.add(VehicleStates::from_cached(
+        offspring,
+        params.evaluations_per_vehicle,
+        params.track_count(),
+        &track,
+    ));
+}
+
+//None if curriculum mode is off. The goal is measured on the main track, which is the first one of the track set.
+fn curriculum_goal(
+    params: &SimulationParams,
+    sim: &GeneticsSimulator,
+    tracks: &Tracks,
+    track: &TrackMetadata,
+) -> Option<i64> {
+    let finish_distance = tracks
+        .get(0)
+        .map_or(track.finish_distance(), |t| t.metadata.finish_distance());
+    if params.curriculum.enabled {
+        Some(
+            params
+                .curriculum
+                .goal(sim.curriculum_stage(), finish_distance),
+        )
+    } else {
+        None
+    }
+}
+
+//Everything that happens once a new entry was added to the statistics, a generation or a steady-state report
+fn after_generation(
+    sim: &mut GeneticsSimulator,
+    params: &SimulationParams,
+    playback: &mut PlaybackState,
+) {
+    if sim.stagnant_generations() == params.stagnation_warning as usize {
+        warn!(
+            "no new best fitness for {} generations, the population may be stuck (diversity: {:?})",
+            params.stagnation_warning,
+            sim.get_generational_statistics()
+                .last()
+                .map(|s| &s.diversity)
+        );
+    }
+
+    if let Some(stats) = sim.get_generational_statistics().last() {
+        if stats.curriculum_goal.is_some()
+            && stats.reached_goal >= params.curriculum.promotion_fraction as f64
+            && !params.curriculum.is_last_stage(stats.curriculum_stage)
+        {
+            info!(
+                "{:.0}% of the population reached the goal of {}",
+                stats.reached_goal * 100.,
+                stats.curriculum_goal.unwrap()
+            );
+            sim.advance_curriculum();
+        }
+    }
+
+    if let Some(target) = playback.pause_at_generation {
+        if sim.generation() >= target {
+            info!("ran until generation {}, pausing", target);
+            playback.pause();
+        }
+    }
+}
+
+//With the island model, the vehicles of all islands are evaluated side by side
+fn states_by_island(
+    population: Pop,
+    islands: Option<Vec<std::ops::Range<usize>>>,
+    make_states: impl Fn(Pop) -> VehicleStates,
+) -> VehicleStates {
+    match islands {
+        Some(ranges) => VehicleStates::interleave_islands(
+            ranges
+                .into_iter()
+                .map(|range| make_states(population[range].to_vec()))
+                .collect(),
+        ),
+        None => make_states(population),
+    }
 }
@@ -0,0 +1,85 @@
+use crate::{
+    genetics_simulator::GeneticsSimulator,
+    hall_of_fame::HallOfFame,
+    plugins::{
+        genealogy::FamilyTreeGui,
+        genetics::{fitness_to_color, SimulationParams},
+        terrain_mesh::Tracks,
+    },
+    vehicle::Vehicle,
+    vehicle_states::VehicleStates,
+};
+use bevy::prelude::*;
+use bevy_egui::{
+    egui::{self, Pos2, ScrollArea},
+    EguiContext,
+};
+
+use log::{debug, error, info, trace, warn}; //IMPORTANT or you won't get any output during tests!
+
+//Shows the best vehicles of all generations. The hall of fame itself is filled in by the genetics plugin.
+pub struct HallOfFamePlugin;
+
+impl Plugin for HallOfFamePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(HallOfFame::<Vehicle>::new(
+            SimulationParams::default().hall_of_fame_size as usize,
+        ))
+        .add_system(resize_hall_of_fame.system())
+        .add_system(make_hall_of_fame_gui.system());
+    }
+}
+
+fn resize_hall_of_fame(params: Res<SimulationParams>, mut hall_of_fame: ResMut<HallOfFame>) {
+    if hall_of_fame.size() != params.hall_of_fame_size as usize {
+        hall_of_fame.resize(params.hall_of_fame_size as usize);
+    }
+}
+
+fn make_hall_of_fame_gui(
+    egui_context: ResMut<EguiContext>,
+    hall_of_fame: Res<HallOfFame>,
+    mut sim: ResMut<GeneticsSimulator>,
+    mut vehicle_states: ResMut<VehicleStates>,
+    tracks: Res<Tracks>,
+    mut family_tree: ResMut<FamilyTreeGui>,
+) {
+    egui::Window::new("Hall of Fame")
+        .default_pos(Pos2::new(400., 20.))
+        .show(egui_context.ctx(), |ui| {
+            if hall_of_fame.entries().is_empty() {
+                ui.label("Nothing evaluated yet");
+                return;
+            }
+            ui.label("Replay drives a vehicle on the current track, Inject puts it into the next generation.");
+            ui.label("Ancestors shows its family tree.");
+
+            ScrollArea::from_max_height(200.).show(ui, |ui| {
+                for (i, entry) in hall_of_fame.entries().iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.label(format!("#{:<2}", i + 1))
+                            .on_hover_ui(|ui| {
+                                ui.monospace(format!("{}", entry.genome));
+                            });
+                        ui.colored_label(
+                            fitness_to_color(entry.fitness as f64),
+                            format!("{:#5}", entry.fitness),
+                        );
+                        ui.label(format!("generation {:#3}", entry.generation + 1));
+
+                        if ui.button("Replay").clicked() {
+                            info!("replaying hall of fame entry {}", i + 1);
+                            vehicle_states.request(entry.genome.clone(), tracks.active);
+                        }
+                        if ui.button("Inject").clicked() {
+                            info!("injecting hall of fame entry {}", i + 1);
+                            sim.inject(entry.genome.clone()); //Spawned with the next generation or offspring
+                        }
+                        if ui.button("Ancestors").clicked() {
+                            family_tree.selected = sim.genealogy().id(&entry.genome);
+                        }
+                    });
+                }
+            });
+        });
+}
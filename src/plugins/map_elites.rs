@@ -0,0 +1,159 @@
+use crate::{
+    map_elites::{EliteArchive, MapElitesParams},
+    plugins::{
+        genetics::{fitness_to_color, SimulationParams},
+        terrain_mesh::Tracks,
+    },
+    vehicle_states::VehicleStates,
+};
+use bevy::prelude::*;
+use bevy_egui::{
+    egui::{self, Pos2},
+    EguiContext,
+};
+use std::path::Path;
+
+use log::{debug, error, info, trace, warn}; //IMPORTANT or you won't get any output during tests!
+
+//Shows the MAP-Elites archive as a heatmap: one cell per kind of vehicle, colored by the fitness of its elite.
+//The archive itself is filled in by the genetics plugin, every time a generation is done.
+pub struct MapElitesPlugin;
+
+impl Plugin for MapElitesPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(EliteArchive::new(&MapElitesParams::default()))
+            .init_resource::<MapElitesGui>()
+            .add_system(regrid_archive.system())
+            .add_system(make_archive_gui.system());
+    }
+}
+
+pub struct MapElitesGui {
+    pub path: String,
+    status: String, //Shown in the GUI, e.g. whether saving worked
+}
+
+impl Default for MapElitesGui {
+    fn default() -> Self {
+        MapElitesGui {
+            path: "assets/elites.json".to_owned(),
+            status: "".to_owned(),
+        }
+    }
+}
+
+//Changing the features or the number of cells sorts the elites into the new grid
+fn regrid_archive(params: Res<SimulationParams>, mut archive: ResMut<EliteArchive>) {
+    let grid = &params.map_elites;
+    if archive.features() != [grid.x_feature, grid.y_feature]
+        || archive.bins() != grid.bins as usize
+    {
+        info!("sorting the elites into a new grid: {:?}", grid);
+        *archive = archive.regrid(grid);
+    }
+}
+
+fn make_archive_gui(
+    egui_context: ResMut<EguiContext>,
+    mut archive: ResMut<EliteArchive>,
+    mut gui: ResMut<MapElitesGui>,
+    mut params: ResMut<SimulationParams>,
+    mut vehicle_states: ResMut<VehicleStates>,
+    tracks: Res<Tracks>,
+) {
+    egui::Window::new("MAP-Elites")
+        .default_pos(Pos2::new(400., 300.))
+        .show(egui_context.ctx(), |ui| {
+            let [x_feature, y_feature] = archive.features();
+            let [width, height] = archive.size();
+            let bins = archive.bins();
+
+            ui.label(format!(
+                "{} elites, {:.0}% of the cells. Click an elite to drive it on the current track.",
+                archive.elites().len(),
+                archive.coverage() * 100.
+            ));
+            ui.label(format!("↑ {:?}, → {:?}", y_feature, x_feature));
+
+            egui::Grid::new("Elite Grid")
+                .spacing([2., 2.])
+                .show(ui, |ui| {
+                    for y in (0..height).rev() {
+                        ui.label(y_feature.bin_label(y, bins));
+                        for x in 0..width {
+                            let elite = match archive.get([x, y]) {
+                                Some(elite) => elite,
+                                None => {
+                                    ui.label("  ");
+                                    continue;
+                                }
+                            };
+
+                            let button = egui::Button::new("  ")
+                                .fill(Some(fitness_to_color(elite.fitness as f64)));
+                            let response = ui.add(button).on_hover_ui(|ui| {
+                                ui.label(format!(
+                                    "{:?} {}, {:?} {}: fitness {} (generation {})",
+                                    x_feature,
+                                    x_feature.measure(&elite.vehicle),
+                                    y_feature,
+                                    y_feature.measure(&elite.vehicle),
+                                    elite.fitness,
+                                    elite.generation + 1
+                                ));
+                                ui.monospace(format!("{}", elite.vehicle));
+                            });
+                            if response.clicked() {
+                                info!("spawning the elite of cell {:?}", elite.cell);
+                                vehicle_states.request(elite.vehicle.clone(), tracks.active);
+                            }
+                        }
+                        ui.end_row();
+                    }
+
+                    ui.label("");
+                    for x in 0..width {
+                        ui.label(x_feature.bin_label(x, bins));
+                    }
+                    ui.end_row();
+                });
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.label("File:");
+                ui.text_edit_singleline(&mut gui.path);
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Save").clicked() {
+                    gui.status = match archive.save(Path::new(&gui.path)) {
+                        Ok(()) => format!("saved to {}", gui.path),
+                        Err(e) => {
+                            error!("{}", e);
+                            e
+                        }
+                    };
+                }
+                if ui.button("Load").clicked() {
+                    gui.status = match EliteArchive::load(Path::new(&gui.path)) {
+                        Ok(loaded) => {
+                            //Use the grid of the file, instead of sorting the elites into the current grid
+                            let [x_feature, y_feature] = loaded.features();
+                            params.map_elites.x_feature = x_feature;
+                            params.map_elites.y_feature = y_feature;
+                            params.map_elites.bins = loaded.bins() as u32;
+                            *archive = loaded;
+                            format!("loaded {}", gui.path)
+                        }
+                        Err(e) => {
+                            error!("{}", e);
+                            e
+                        }
+                    };
+                }
+            });
+
+            if !gui.status.is_empty() {
+                ui.label(gui.status.as_str());
+            }
+        });
+}
@@ -1,6 +1,14 @@
 pub mod background;
 pub mod camera;
+pub mod genealogy;
 pub mod genetics;
+pub mod hall_of_fame;
+pub mod map_elites;
+pub mod obstacles;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod parallel_evaluation;
+pub mod simulation_clock;
 pub mod spawn_indicator;
+pub mod terrain_editor;
 pub mod terrain_mesh;
 pub mod vehicle_manager;
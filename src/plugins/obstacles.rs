@@ -0,0 +1,162 @@
+use bevy::prelude::*;
+use bevy_prototype_lyon::prelude::*;
+use bevy_rapier2d::{
+    physics::{PhysicsStages, PhysicsSystems},
+    prelude::*,
+};
+
+use log::{debug, error, info, trace, warn}; //IMPORTANT or you won't get any output during tests!
+
+use crate::{
+    track::obstacles::{obstacle_parts, platform_offset, Obstacle, PartShape},
+    vehicle_states::VehicleID,
+};
+
+//Moving platforms, pendulums, seesaws and boulders from the track data.
+//They get spawned together with every batch of vehicles (see vehicle_manager), so every batch starts from the same state.
+pub struct ObstaclePlugin;
+
+impl Plugin for ObstaclePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        //Runs once per physics step, so platforms move the same way no matter the simulation speed.
+        //Before the step, like HeadlessWorld::step, so both backends see the platforms in the same place.
+        app.add_system_to_stage(
+            PhysicsStages::StepWorld,
+            move_platforms.system().before(PhysicsSystems::StepWorld),
+        );
+    }
+}
+
+#[derive(new)]
+pub struct ObstacleComponent {
+    pub belongs_to: VehicleID, //Every vehicle gets its own copy of the obstacles
+}
+
+pub struct MovingPlatform {
+    start: Vec2, //Physics units
+    travel: [f32; 2],
+    period: f32,
+    steps: u32, //Physics steps since the platform was spawned
+}
+
+//Spawns a copy of every obstacle that only collides with the vehicle in the given collision group
+pub fn spawn_obstacles(
+    cmd: &mut Commands,
+    obstacles: &[Obstacle],
+    vehicle_id: VehicleID,
+    collider_group: u32,
+    color: Color,
+    sim_scale: f32,
+) {
+    let mut color = color;
+    color.set_a(0.6);
+
+    for obstacle in obstacles {
+        let (parts, joints) = obstacle_parts(obstacle);
+
+        let entities = parts
+            .iter()
+            .map(|part| {
+                let rigid_body = RigidBodyBundle {
+                    body_type: part.body.into(),
+                    position: (Vec2::from(part.position) / sim_scale).into(),
+                    activation: RigidBodyActivation::cannot_sleep(), //Kinematic platforms have to keep moving
+                    ..Default::default()
+                };
+
+                let mut builder = GeometryBuilder::new();
+                let fill_color = match &part.shape {
+                    PartShape::Ball { radius } => {
+                        builder.add(&shapes::Circle {
+                            radius: *radius,
+                            center: Vec2::ZERO,
+                        });
+                        color
+                    }
+                    PartShape::Box { size } => {
+                        let (x, y) = (size[0] / 2., size[1] / 2.);
+                        builder.add(&shapes::Polygon {
+                            points: vec![
+                                Vec2::new(-x, -y),
+                                Vec2::new(x, -y),
+                                Vec2::new(x, y),
+                                Vec2::new(-x, y),
+                            ],
+                            closed: true,
+                        });
+                        color
+                    }
+                    PartShape::Pivot => {
+                        builder.add(&shapes::Circle {
+                            radius: 8.,
+                            center: Vec2::ZERO,
+                        });
+                        Color::rgba(0., 0., 0., 0.5)
+                    }
+                };
+                let shape_bundle = builder.build(
+                    ShapeColors::new(fill_color),
+                    DrawMode::Fill(FillOptions::DEFAULT),
+                    Transform::from_translation(Vec3::new(0., 0., 30.)),
+                );
+
+                let mut ent = cmd.spawn_bundle(rigid_body);
+                ent.insert_bundle(shape_bundle)
+                    .insert(RigidBodyPositionSync::Discrete)
+                    .insert(Name::new("Obstacle".to_owned()))
+                    .insert(ObstacleComponent::new(vehicle_id));
+
+                if let Some(shape) = part.shape.collider_shape(sim_scale) {
+                    ent.insert_bundle(ColliderBundle {
+                        shape,
+                        material: ColliderMaterial::new(0.5, 0.1),
+                        mass_properties: ColliderMassProps::Density(part.density),
+                        flags: ColliderFlags {
+                            collision_groups: InteractionGroups::new(
+                                collider_group,
+                                collider_group,
+                            ),
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    });
+                }
+
+                if let Obstacle::MovingPlatform { travel, period, .. } = obstacle {
+                    ent.insert(MovingPlatform {
+                        start: Vec2::from(part.position) / sim_scale,
+                        travel: [travel[0] / sim_scale, travel[1] / sim_scale],
+                        period: *period,
+                        steps: 0,
+                    });
+                }
+
+                ent.id()
+            })
+            .collect::<Vec<_>>();
+
+        for joint in joints {
+            let ball_joint = BallJoint::new(
+                (Vec2::from(joint.anchor_a) / sim_scale).into(),
+                (Vec2::from(joint.anchor_b) / sim_scale).into(),
+            );
+            cmd.spawn().insert(JointBuilderComponent::new(
+                ball_joint,
+                entities[joint.a],
+                entities[joint.b],
+            ));
+        }
+    }
+}
+
+fn move_platforms(
+    mut query: Query<(&mut RigidBodyPosition, &mut MovingPlatform)>,
+    integration_parameters: Res<IntegrationParameters>,
+) {
+    for (mut rb_pos, mut platform) in query.iter_mut() {
+        platform.steps += 1;
+        let time = platform.steps as f32 * integration_parameters.dt;
+        let [dx, dy] = platform_offset(platform.travel, platform.period, time);
+        rb_pos.next_position = Isometry::translation(platform.start.x + dx, platform.start.y + dy);
+    }
+}
@@ -0,0 +1,230 @@
+use crate::{
+    fitness::GlobalFitnessMap,
+    genetics_simulator::GeneticsSimulator,
+    headless_world::{terrain_collider_shape, HeadlessWorld},
+    plugins::{
+        genetics::SimulationParams,
+        simulation_clock::PlaybackState,
+        terrain_mesh::{LoadedTrack, TerrainTriangles, Tracks},
+    },
+    track::TrackMetadata,
+    vehicle::Vehicle,
+    vehicle_states::{VehicleID, VehicleStates, VehicleStatus},
+};
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use crossbeam_channel::{Receiver, Sender};
+use std::{sync::Arc, thread};
+
+use log::{debug, error, info, trace, warn}; //IMPORTANT or you won't get any output during tests!
+
+//Evaluates vehicles headless on several worker threads, each with its own rapier pipeline.
+//Not available on WASM since it has no threads.
+pub struct ParallelEvaluationPlugin;
+
+impl Plugin for ParallelEvaluationPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system(start_parallel_evaluator.system());
+        app.add_system(dispatch_pending_vehicles.system());
+        app.add_system(collect_evaluation_results.system());
+    }
+}
+
+struct EvaluationJob {
+    vehicle_id: VehicleID,
+    vehicle: Vehicle,
+    track_index: usize,
+    track: LoadedTrack, //Every vehicle can be on another track
+    spawn: [f32; 2],    //Spawn point plus the jitter offset, in pixels
+    duration: f32,      //Simulated seconds
+    generation: usize,  //Generation the vehicle belongs to, for the fitness cache
+}
+
+struct EvaluationResult {
+    vehicle_id: VehicleID,
+    fitness: i64,
+    fell_apart: bool,
+    track: TrackMetadata, //To check whether the vehicle finished
+}
+
+const TERRAIN_CACHE_SIZE: usize = 16; //Colliders every worker keeps around, one per track
+
+pub struct ParallelEvaluator {
+    jobs: Sender<EvaluationJob>,
+    results: Receiver<EvaluationResult>,
+}
+
+impl ParallelEvaluator {
+    pub fn new(worker_count: usize, scale: f32, fitness_map: GlobalFitnessMap) -> Self {
+        let (job_tx, job_rx) = crossbeam_channel::unbounded::<EvaluationJob>();
+        let (result_tx, result_rx) = crossbeam_channel::unbounded();
+
+        for worker in 0..worker_count {
+            let job_rx = job_rx.clone();
+            let result_tx = result_tx.clone();
+            let fitness_map = fitness_map.clone();
+
+            thread::Builder::new()
+                .name(format!("evaluation worker {}", worker))
+                .spawn(move || {
+                    //Every worker builds its own copy of the terrain colliders, and only builds them once per track
+                    let mut terrain_cache: Vec<(TerrainTriangles, ColliderShape)> = vec![];
+
+                    //Runs until the evaluator (and thus the job sender) is dropped
+                    for job in job_rx.iter() {
+                        let terrain = &job.track.triangles;
+                        let cached = terrain_cache
+                            .iter()
+                            .position(|(triangles, _)| Arc::ptr_eq(&triangles.0, &terrain.0));
+                        let index = match cached {
+                            Some(index) => index,
+                            None => {
+                                if terrain_cache.len() >= TERRAIN_CACHE_SIZE {
+                                    terrain_cache.remove(0);
+                                }
+                                let shape = terrain_collider_shape(&terrain.0, scale);
+                                terrain_cache.push((terrain.clone(), shape));
+                                terrain_cache.len() - 1
+                            }
+                        };
+                        let (_, terrain_shape) = &terrain_cache[index];
+
+                        let (fitness, fell_apart, behavior) = HeadlessWorld::evaluate(
+                            terrain_shape,
+                            scale,
+                            &job.vehicle,
+                            job.spawn,
+                            &job.track.metadata.obstacles,
+                            job.duration,
+                        );
+                        info!(
+                            "worker {} evaluated vehicle {:?}: fitness {}",
+                            worker, job.vehicle_id, fitness
+                        );
+
+                        let mut record = fitness_map.entry(job.vehicle).or_default();
+                        record.push(job.track_index, fitness, job.generation);
+                        record.push_behavior(&behavior);
+                        drop(record); //Don't hold the map entry locked while sending
+
+                        let result = EvaluationResult {
+                            vehicle_id: job.vehicle_id,
+                            fitness,
+                            fell_apart,
+                            track: job.track.metadata,
+                        };
+                        if result_tx.send(result).is_err() {
+                            break; //Evaluator is gone
+                        }
+                    }
+                })
+                .expect("couldn't spawn evaluation worker");
+        }
+
+        ParallelEvaluator {
+            jobs: job_tx,
+            results: result_rx,
+        }
+    }
+}
+
+fn start_parallel_evaluator(
+    mut commands: Commands,
+    params: Res<SimulationParams>,
+    evaluator: Option<Res<ParallelEvaluator>>,
+    configuration: Res<RapierConfiguration>,
+    fitness_map: Res<GlobalFitnessMap>,
+) {
+    if !params.uses_parallel_backend() || evaluator.is_some() {
+        return; //NOTE: worker_threads is only read once, when the evaluator starts
+    }
+
+    let worker_count = params.worker_threads as usize;
+    info!("starting parallel evaluator with {} workers", worker_count);
+    commands.insert_resource(ParallelEvaluator::new(
+        worker_count,
+        configuration.scale,
+        fitness_map.clone(),
+    ));
+}
+
+fn dispatch_pending_vehicles(
+    params: Res<SimulationParams>,
+    evaluator: Option<Res<ParallelEvaluator>>,
+    tracks: Res<Tracks>,
+    mut vehicle_states: ResMut<VehicleStates>,
+    sim: Res<GeneticsSimulator>,
+    mut playback: ResMut<PlaybackState>,
+) {
+    if !params.uses_parallel_backend() {
+        return;
+    }
+
+    if playback.paused {
+        return; //Vehicles that were already handed out still finish
+    }
+    if playback.pause_after_batch {
+        //Don't hand out new vehicles, pause once the ones that were already handed out are done
+        let any_running = vehicle_states
+            .get_vehicle_states()
+            .iter()
+            .any(|state| state.status == VehicleStatus::Running);
+        if !any_running {
+            playback.pause();
+        }
+        return;
+    }
+
+    //Wait until the tracks have been loaded
+    if let (Some(evaluator), false) = (evaluator, tracks.tracks.is_empty()) {
+        let pending = vehicle_states.get_vehicle_states().len();
+        for popped in vehicle_states.pop_vehicles(pending, params.spawn_jitter, None) {
+            let track = match tracks.get(popped.track) {
+                Some(track) => track.clone(),
+                None => {
+                    //It stays Running, so the generation stalls instead of using a fitness from another track
+                    error!(
+                        "vehicle {:?} is on track {}, but only {} tracks are loaded",
+                        popped.id,
+                        popped.track,
+                        tracks.tracks.len()
+                    );
+                    continue;
+                }
+            };
+            let job = EvaluationJob {
+                vehicle_id: popped.id,
+                vehicle: popped.vehicle,
+                track_index: popped.track,
+                spawn: [
+                    track.metadata.spawn_point[0] + popped.spawn_offset,
+                    track.metadata.spawn_point[1],
+                ],
+                duration: track.metadata.time_limit_or(params.max_generation_duration),
+                generation: sim.generation(),
+                track,
+            };
+            evaluator
+                .jobs
+                .send(job)
+                .expect("evaluation workers have shut down");
+        }
+    }
+}
+
+fn collect_evaluation_results(
+    evaluator: Option<Res<ParallelEvaluator>>,
+    mut vehicle_states: ResMut<VehicleStates>,
+) {
+    if let Some(evaluator) = evaluator {
+        for result in evaluator.results.try_iter() {
+            vehicle_states.set_fitness(
+                result.vehicle_id,
+                result.fitness,
+                result.fell_apart,
+                &result.track,
+            );
+            vehicle_states.finalize_vehicle(result.vehicle_id); //Fitness map is already filled in by the worker
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use bevy::{ecs::schedule::ShouldRun, prelude::*};
+use bevy_rapier2d::{physics::PhysicsStages, prelude::*};
+use instant::{Duration, Instant};
+
+use log::{debug, error, info, trace, warn}; //IMPORTANT or you won't get any output during tests!
+
+use super::genetics::SimulationParams;
+
+//Decouples the simulation from the frame rate: runs as many fixed physics steps per frame as the simulation speed demands.
+//Must be added after the RapierPhysicsPlugin, since it changes the physics step stage.
+pub struct SimulationClockPlugin;
+
+impl Plugin for SimulationClockPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<SimulationClock>();
+        app.init_resource::<PlaybackState>();
+        app.add_system_to_stage(CoreStage::First, start_frame.system());
+        app.stage(PhysicsStages::StepWorld, |stage: &mut SystemStage| {
+            stage.set_run_criteria(run_physics_substep.system())
+        });
+    }
+}
+
+const MAX_STEPS_PER_FRAME: f64 = 64.; //So a slow frame can't make the next one even slower
+const FAST_FRAME_BUDGET: f32 = 1. / 60.; //Seconds of physics per frame when running as fast as possible
+
+#[derive(Default)]
+pub struct SimulationClock {
+    delta: Duration, //Simulated time that passed this frame
+    steps_owed: f64, //Physics steps we still have to run to keep up with the simulation speed
+    frame_start: Option<Instant>,
+}
+
+//Pause and step controls, set from the Genetics GUI
+pub struct PlaybackState {
+    pub paused: bool,
+    pub pending_ticks: u32,      //Physics steps to run while paused
+    pub pause_after_batch: bool, //Pause as soon as the current batch of vehicles is done
+    pub pause_at_generation: Option<usize>,
+    pub generations_to_run: u32, //Used by the "run N generations" button
+}
+
+impl Default for PlaybackState {
+    fn default() -> Self {
+        PlaybackState {
+            paused: false,
+            pending_ticks: 0,
+            pause_after_batch: false,
+            pause_at_generation: None,
+            generations_to_run: 5,
+        }
+    }
+}
+
+impl PlaybackState {
+    pub fn pause(&mut self) {
+        self.paused = true;
+        self.pause_after_batch = false;
+        self.pause_at_generation = None;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+}
+
+impl SimulationClock {
+    //Use this instead of Time::delta() for anything that should follow the simulation instead of the wall clock
+    pub fn delta(&self) -> Duration {
+        self.delta
+    }
+}
+
+fn start_frame(
+    mut clock: ResMut<SimulationClock>,
+    params: Res<SimulationParams>,
+    playback: Res<PlaybackState>,
+    time: Res<Time>,
+    integration_parameters: Res<IntegrationParameters>,
+    mut configuration: ResMut<RapierConfiguration>,
+) {
+    configuration.physics_pipeline_active = !playback.paused || playback.pending_ticks > 0;
+
+    let steps = if playback.paused {
+        0. //Don't catch up on the paused time after resuming
+    } else {
+        params.simulation_speed as f64 * time.delta_seconds_f64() / integration_parameters.dt as f64
+    };
+
+    clock.steps_owed = (clock.steps_owed + steps).min(MAX_STEPS_PER_FRAME);
+    clock.delta = Duration::default();
+    clock.frame_start = Some(Instant::now());
+}
+
+fn run_physics_substep(
+    mut clock: ResMut<SimulationClock>,
+    mut playback: ResMut<PlaybackState>,
+    params: Res<SimulationParams>,
+    integration_parameters: Res<IntegrationParameters>,
+) -> ShouldRun {
+    let run = if playback.paused {
+        if playback.pending_ticks > 0 {
+            playback.pending_ticks -= 1;
+            true
+        } else {
+            false
+        }
+    } else if params.as_fast_as_possible {
+        let elapsed = clock
+            .frame_start
+            .map_or(0., |start| start.elapsed().as_secs_f32());
+        elapsed < FAST_FRAME_BUDGET
+    } else if clock.steps_owed >= 1. {
+        clock.steps_owed -= 1.;
+        true
+    } else {
+        false
+    };
+
+    if run {
+        clock.delta += Duration::from_secs_f32(integration_parameters.dt);
+        ShouldRun::YesAndCheckAgain
+    } else {
+        ShouldRun::No
+    }
+}
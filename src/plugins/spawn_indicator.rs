@@ -1,5 +1,7 @@
 use bevy::prelude::*;
 use bevy_prototype_lyon::prelude::*;
+
+use crate::track::TrackMetadata;
 use log::{debug, error, info, trace, warn};
 
 //NOTE: I dunno why but this plugin is vital for WASM.
@@ -8,15 +10,31 @@ use log::{debug, error, info, trace, warn};
 pub struct SpawnIndicatorPlugin;
 impl Plugin for SpawnIndicatorPlugin {
     fn build(&self, app: &mut AppBuilder) {
-        app.add_startup_system(setup_shape.system());
+        app.add_system(setup_shape.system());
     }
 }
 
-fn setup_shape(mut commands: Commands) {
+const BOX_OFFSET_Y: f32 = 200.; //Vehicles are mostly above their center, so the box is too
+
+pub struct SpawnIndicatorComponent;
+
+//Rebuilt whenever another track is loaded
+fn setup_shape(
+    mut commands: Commands,
+    metadata: Res<TrackMetadata>,
+    query: Query<Entity, With<SpawnIndicatorComponent>>,
+) {
+    if !metadata.is_changed() {
+        return;
+    }
+    for e in query.iter() {
+        commands.entity(e).despawn();
+    }
+
     let mut points = vec![];
 
-    let x = 300.;
-    let y = 300.;
+    let x = metadata.spawn_area[0] / 2.;
+    let y = metadata.spawn_area[1] / 2.;
 
     points.push(Vec2::new(x, y));
     points.push(Vec2::new(x, -y));
@@ -43,10 +61,15 @@ fn setup_shape(mut commands: Commands) {
     let shape_bundle = builder.build(
         ShapeColors::outlined(Color::WHITE, Color::WHITE),
         DrawMode::Stroke(stroke),
-        Transform::from_translation(Vec3::new(0., 200., 50.)),
+        Transform::from_translation(Vec3::new(
+            metadata.spawn_point[0],
+            metadata.spawn_point[1] + BOX_OFFSET_Y,
+            50.,
+        )),
     );
 
     commands
         .spawn_bundle(shape_bundle)
-        .insert(Name::new("Spawn".to_owned()));
+        .insert(Name::new("Spawn".to_owned()))
+        .insert(SpawnIndicatorComponent);
 }
@@ -0,0 +1,381 @@
+use bevy::{prelude::*, render::camera::Camera};
+use bevy_egui::{egui, EguiContext};
+use bevy_prototype_lyon::prelude::*;
+use std::path::Path;
+
+use log::{debug, error, info, trace, warn}; //IMPORTANT or you won't get any output during tests!
+
+use super::terrain_mesh::{LoadedTrack, Tracks};
+use crate::{
+    track::{
+        loader::{save_track, SurfaceData, TrackFile},
+        TrackMetadata, TrackSurface,
+    },
+    utility::window_to_world,
+};
+
+//Lets you click around in the world to change the track while the simulation keeps running.
+//Every change replaces the edited track in Tracks, so the collider and the shapes get rebuilt right away.
+pub struct TerrainEditorPlugin;
+
+impl Plugin for TerrainEditorPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<TerrainEditor>()
+            .add_system(make_editor_gui.system())
+            .add_system(edit_with_mouse.system())
+            .add_system(draw_vertex_handles.system());
+    }
+}
+
+const GRAB_RADIUS: f32 = 15.; //Screen pixels, how close you have to click to a vertex to grab it
+const HANDLE_RADIUS: f32 = 10.;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum EditorTool {
+    Vertices,    //Left click: add or drag a vertex, right click: delete it
+    SpawnPoint,  //Left click: move the spawn point
+    FinishLine,  //Left click: move the finish line
+    Checkpoints, //Left click: add a checkpoint, right click: delete it
+}
+
+pub struct TerrainEditor {
+    pub enabled: bool,
+    pub tool: EditorTool,
+    pub save_path: String,
+    surface: TrackSurface, //Empty until editing starts
+    metadata: TrackMetadata,
+    track: usize,                     //Index of the edited track in Tracks
+    dragging: Option<(usize, usize)>, //Piece and index of the vertex that is being dragged
+    revision: u32, //Goes up with every change, so the handles only get redrawn when needed
+    status: String, //Shown in the GUI, e.g. whether saving worked
+}
+
+impl Default for TerrainEditor {
+    fn default() -> Self {
+        TerrainEditor {
+            enabled: false,
+            tool: EditorTool::Vertices,
+            save_path: "assets/tracks/edited.json".to_owned(),
+            surface: vec![],
+            metadata: TrackMetadata::default(),
+            track: 0,
+            dragging: None,
+            revision: 0,
+            status: "".to_owned(),
+        }
+    }
+}
+
+impl TerrainEditor {
+    //Starts editing the track that is currently shown
+    fn load(&mut self, tracks: &Tracks) {
+        let loaded = match tracks.get(tracks.active) {
+            Some(loaded) => loaded,
+            None => return, //Nothing loaded yet
+        };
+
+        self.track = tracks.active;
+        self.metadata = loaded.metadata.clone();
+        self.surface = match &loaded.surface {
+            Some(surface) => (**surface).clone(),
+            None => {
+                self.status = format!(
+                    "{} can't be edited, starting from a flat track",
+                    loaded.name
+                );
+                vec![vec![
+                    [-1500., -300.],
+                    [self.metadata.finish_x + 600., -300.],
+                ]]
+            }
+        };
+        self.dragging = None;
+        self.revision += 1;
+    }
+
+    fn to_track(&self) -> LoadedTrack {
+        let mut metadata = self.metadata.clone();
+        metadata.finish_flag = None; //The surface might have changed below it
+        let metadata = metadata.with_flag_on(&self.surface);
+        LoadedTrack::from_surface("Edited track", self.surface.clone(), metadata)
+    }
+
+    fn nearest_vertex(&self, pos: [f32; 2], radius: f32) -> Option<(usize, usize)> {
+        let distance = |v: [f32; 2]| ((v[0] - pos[0]).powi(2) + (v[1] - pos[1]).powi(2)).sqrt();
+
+        self.surface
+            .iter()
+            .enumerate()
+            .flat_map(|(p, piece)| piece.iter().enumerate().map(move |(i, v)| (p, i, *v)))
+            .filter(|(_, _, v)| distance(*v) < radius)
+            .min_by(|a, b| distance(a.2).partial_cmp(&distance(b.2)).unwrap())
+            .map(|(p, i, _)| (p, i))
+    }
+
+    //Adds the vertex to the piece below or next to it, keeping every piece sorted from left to right
+    fn insert_vertex(&mut self, pos: [f32; 2]) -> (usize, usize) {
+        let x = pos[0];
+        let first_x = |piece: &Vec<[f32; 2]>| piece.first().map_or(0., |v| v[0]);
+        let last_x = |piece: &Vec<[f32; 2]>| piece.last().map_or(0., |v| v[0]);
+
+        let p = self
+            .surface
+            .iter()
+            .position(|piece| x >= first_x(piece) && x <= last_x(piece))
+            .unwrap_or_else(|| {
+                //In a gap or past the ends: the piece with the closest end
+                let gap_to = |piece: &Vec<[f32; 2]>| {
+                    (first_x(piece) - x).abs().min((last_x(piece) - x).abs())
+                };
+                (0..self.surface.len())
+                    .min_by(|a, b| {
+                        gap_to(&self.surface[*a])
+                            .partial_cmp(&gap_to(&self.surface[*b]))
+                            .unwrap()
+                    })
+                    .unwrap_or(0)
+            });
+
+        let piece = &mut self.surface[p];
+        let i = piece.iter().position(|v| v[0] > x).unwrap_or(piece.len());
+        piece.insert(i, pos);
+        (p, i)
+    }
+
+    fn move_vertex(&mut self, (p, i): (usize, usize), pos: [f32; 2]) {
+        let piece = &mut self.surface[p];
+
+        //Can't move past the neighbours, or the surface wouldn't go from left to right anymore
+        let min_x = if i > 0 {
+            piece[i - 1][0] + 1.
+        } else {
+            f32::MIN
+        };
+        let max_x = piece.get(i + 1).map_or(f32::MAX, |v| v[0] - 1.);
+        piece[i] = [pos[0].clamp(min_x, max_x), pos[1]];
+    }
+
+    fn delete_vertex(&mut self, (p, i): (usize, usize)) {
+        let total = self.surface.iter().map(|piece| piece.len()).sum::<usize>();
+        if self.surface[p].len() <= 2 && total - self.surface[p].len() < 2 {
+            self.status = "can't delete the last piece of the track".to_owned();
+            return;
+        }
+
+        self.surface[p].remove(i);
+        if self.surface[p].len() < 2 {
+            self.surface.remove(p);
+        }
+    }
+
+    fn save(&mut self) {
+        let track = self.to_track();
+        let file = TrackFile {
+            surface: SurfaceData::Polyline {
+                pieces: self.surface.clone(),
+            },
+            metadata: Some(track.metadata),
+        };
+
+        self.status = match save_track(Path::new(&self.save_path), &file) {
+            Ok(()) => format!("saved to {}", self.save_path),
+            Err(e) => {
+                error!("{}", e);
+                e
+            }
+        };
+    }
+}
+
+fn make_editor_gui(
+    egui_context: ResMut<EguiContext>,
+    mut editor: ResMut<TerrainEditor>,
+    mut tracks: ResMut<Tracks>,
+) {
+    egui::Window::new("Terrain Editor")
+        .default_pos(egui::Pos2::new(400., 20.))
+        .show(egui_context.ctx(), |ui| {
+            let was_enabled = editor.enabled;
+            ui.checkbox(&mut editor.enabled, "Edit terrain");
+
+            if editor.enabled && !was_enabled {
+                if editor.surface.is_empty() {
+                    editor.load(&tracks);
+                }
+                editor.revision += 1;
+            }
+            if !editor.enabled {
+                if was_enabled {
+                    editor.revision += 1; //Hide the handles
+                }
+                return;
+            }
+
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut editor.tool, EditorTool::Vertices, "Vertices");
+                ui.radio_value(&mut editor.tool, EditorTool::SpawnPoint, "Spawn");
+                ui.radio_value(&mut editor.tool, EditorTool::FinishLine, "Finish");
+                ui.radio_value(&mut editor.tool, EditorTool::Checkpoints, "Checkpoints");
+            });
+            ui.label(match editor.tool {
+                EditorTool::Vertices => {
+                    "Left click to add or drag a vertex, right click to delete it"
+                }
+                EditorTool::SpawnPoint => "Left click to move the spawn point",
+                EditorTool::FinishLine => "Left click to move the finish line",
+                EditorTool::Checkpoints => {
+                    "Left click to add a checkpoint, right click to delete it"
+                }
+            });
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.label("Save as:");
+                ui.text_edit_singleline(&mut editor.save_path);
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Save").clicked() {
+                    editor.save();
+                }
+                if ui.button("Start over from the current track").clicked() {
+                    editor.load(&tracks);
+                    let track = editor.track;
+                    tracks.replace(track, editor.to_track());
+                }
+            });
+
+            if !editor.status.is_empty() {
+                ui.label(editor.status.as_str());
+            }
+        });
+}
+
+fn edit_with_mouse(
+    mut editor: ResMut<TerrainEditor>,
+    mut tracks: ResMut<Tracks>,
+    windows: Res<Windows>,
+    mouse: Res<Input<MouseButton>>,
+    egui_ctx: ResMut<EguiContext>,
+    query_cam: Query<&Transform, With<Camera>>,
+) {
+    if !editor.enabled || editor.surface.is_empty() {
+        return;
+    }
+    if egui_ctx.ctx().wants_pointer_input() {
+        return; //Clicking a button shouldn't add a vertex behind it
+    }
+
+    let window = windows.get_primary().unwrap();
+    let (cursor, cam_transform) = match (window.cursor_position(), query_cam.iter().next()) {
+        (Some(cursor), Some(cam_transform)) => (cursor, cam_transform),
+        _ => return,
+    };
+    let world = window_to_world(cursor, window, cam_transform);
+    let pos = [world.x, world.y];
+    let grab_radius = GRAB_RADIUS * cam_transform.scale.x; //Easier to grab when zoomed out
+
+    let left = mouse.just_pressed(MouseButton::Left);
+    let right = mouse.just_pressed(MouseButton::Right);
+
+    let mut changed = false;
+    match editor.tool {
+        EditorTool::Vertices => {
+            if left {
+                let vertex = match editor.nearest_vertex(pos, grab_radius) {
+                    Some(vertex) => vertex,
+                    None => editor.insert_vertex(pos),
+                };
+                editor.dragging = Some(vertex);
+                changed = true;
+            } else if mouse.pressed(MouseButton::Left) {
+                if let Some(vertex) = editor.dragging {
+                    editor.move_vertex(vertex, pos);
+                    changed = true;
+                }
+            } else if editor.dragging.is_some() {
+                editor.dragging = None;
+            }
+
+            if right {
+                if let Some(vertex) = editor.nearest_vertex(pos, grab_radius) {
+                    editor.delete_vertex(vertex);
+                    changed = true;
+                }
+            }
+        }
+        EditorTool::SpawnPoint => {
+            if left {
+                editor.metadata.spawn_point = pos;
+                changed = true;
+            }
+        }
+        EditorTool::FinishLine => {
+            if left {
+                editor.metadata.finish_x = pos[0];
+                changed = true;
+            }
+        }
+        EditorTool::Checkpoints => {
+            if left {
+                let checkpoints = &mut editor.metadata.checkpoints;
+                checkpoints.push(pos[0]);
+                checkpoints.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                changed = true;
+            }
+            if right {
+                let checkpoints = &mut editor.metadata.checkpoints;
+                if let Some(i) = checkpoints
+                    .iter()
+                    .position(|x| (x - pos[0]).abs() < grab_radius)
+                {
+                    checkpoints.remove(i);
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    if changed {
+        editor.revision += 1;
+        let track = editor.track;
+        tracks.replace(track, editor.to_track());
+    }
+}
+
+pub struct VertexHandleComponent;
+
+fn draw_vertex_handles(
+    mut cmd: Commands,
+    editor: Res<TerrainEditor>,
+    query_handles: Query<Entity, With<VertexHandleComponent>>,
+    mut drawn_revision: Local<u32>,
+) {
+    if *drawn_revision == editor.revision {
+        return;
+    }
+    *drawn_revision = editor.revision;
+
+    for e in query_handles.iter() {
+        cmd.entity(e).despawn();
+    }
+    if !editor.enabled {
+        return;
+    }
+
+    let mut builder = GeometryBuilder::new();
+    for vertex in editor.surface.iter().flatten() {
+        builder.add(&shapes::Circle {
+            radius: HANDLE_RADIUS,
+            center: Vec2::from(*vertex),
+        });
+    }
+    let handles = builder.build(
+        ShapeColors::new(Color::rgba(1., 1., 1., 0.8)),
+        DrawMode::Fill(FillOptions::DEFAULT),
+        Transform::from_translation(Vec3::new(0., 0., 70.)),
+    );
+
+    cmd.spawn_bundle(handles)
+        .insert(Name::new("VertexHandles".to_owned()))
+        .insert(VertexHandleComponent);
+}
@@ -1,28 +1,138 @@
 use bevy::prelude::*;
 use bevy::render::mesh::*;
 use bevy_prototype_lyon::prelude::*;
-use bevy_rapier2d::{na::Point2, prelude::*};
+use bevy_rapier2d::prelude::*;
 use log::{debug, error, info, trace, warn};
+use std::{path::Path, sync::Arc};
+
+use super::genetics::SimulationParams;
+use crate::headless_world::terrain_collider_shape;
+use crate::track::{
+    generator::{generate_track, TrackGeneratorParams},
+    loader::load_track,
+    surface_to_triangles, TrackMetadata, TrackSource, TrackSurface, Triangle,
+};
+use crate::track_evolution::TrackEvolver;
+
+//The triangles the terrain collider was built from, so it can be rebuilt outside of the bevy world
+#[derive(Clone)]
+pub struct TerrainTriangles(pub Arc<Vec<Triangle>>);
+
+#[derive(Clone)]
+pub struct LoadedTrack {
+    pub name: String,
+    pub triangles: TerrainTriangles,
+    pub metadata: TrackMetadata,
+    pub surface: Option<Arc<TrackSurface>>, //None for TerrainRoad, which is only available as triangles
+}
+
+impl LoadedTrack {
+    pub fn new(name: impl Into<String>, triangles: Vec<Triangle>, metadata: TrackMetadata) -> Self {
+        LoadedTrack {
+            name: name.into(),
+            triangles: TerrainTriangles(Arc::new(triangles)),
+            metadata,
+            surface: None,
+        }
+    }
+
+    pub fn from_surface(
+        name: impl Into<String>,
+        surface: TrackSurface,
+        metadata: TrackMetadata,
+    ) -> Self {
+        LoadedTrack {
+            surface: Some(Arc::new(surface.clone())),
+            ..LoadedTrack::new(name, surface_to_triangles(&surface), metadata)
+        }
+    }
+}
+
+//Every track of SimulationParams::track_set, in the order described in TrackSet::track_count.
+//Only one of them is shown at a time, the TrackMetadata resource always belongs to that one.
+#[derive(Default)]
+pub struct Tracks {
+    pub tracks: Vec<LoadedTrack>,
+    pub active: usize,        //Track the rendered simulation wants to show
+    displayed: Option<usize>, //Track the terrain entities were built from
+}
+
+impl Tracks {
+    //None past the end, e.g. while the track set is still loading
+    pub fn get(&self, track: usize) -> Option<&LoadedTrack> {
+        self.tracks.get(track)
+    }
+
+    pub fn name(&self, track: usize) -> &str {
+        self.get(track).map_or("?", |track| track.name.as_str())
+    }
+
+    //Whether the terrain of this track is built, so vehicles can be spawned on it
+    pub fn is_displayed(&self, track: usize) -> bool {
+        self.displayed == Some(track)
+    }
+
+    //Swaps out a single track, e.g. after editing it. The terrain gets rebuilt right away if it's shown.
+    pub fn replace(&mut self, track: usize, loaded: LoadedTrack) {
+        if let Some(old) = self.tracks.get_mut(track) {
+            *old = loaded;
+            if self.displayed == Some(track) {
+                self.displayed = None;
+            }
+        }
+    }
+
+    //Nothing gets spawned until the track set is loaded again, e.g. after the tracks were evolved
+    pub fn clear(&mut self) {
+        self.tracks.clear();
+        self.active = 0;
+        self.displayed = None;
+    }
+}
+
+//Triangles of models/TerrainRoad.glb, available once the mesh is loaded
+pub struct GltfTriangles(Vec<Triangle>);
+
+pub struct TerrainComponent;
+
+//Finish flag and checkpoint lines, respawned whenever the TrackMetadata changes
+pub struct TrackMarkerComponent;
+
 pub struct TerrainMeshPlugin;
 
 impl Plugin for TerrainMeshPlugin {
     fn build(&self, app: &mut AppBuilder) {
-        app.add_system(spawn_meshes.system())
-            .add_startup_system(spawn_finish_flag.system());
+        app.init_resource::<TrackMetadata>()
+            .init_resource::<Tracks>()
+            .add_system(spawn_meshes.system())
+            .add_system(update_terrain.system())
+            .add_system(update_track_markers.system());
     }
 }
 
-fn spawn_finish_flag(
+fn update_track_markers(
     asset_server: Res<AssetServer>,
     mut materials: ResMut<Assets<ColorMaterial>>,
     mut cmd: Commands,
+    metadata: Res<TrackMetadata>,
+    query_markers: Query<Entity, With<TrackMarkerComponent>>,
 ) {
+    if !metadata.is_changed() {
+        return;
+    }
+    for e in query_markers.iter() {
+        cmd.entity(e).despawn();
+    }
+
     let finish_tex = asset_server.load("textures/finish.png");
     let scale = 1.5;
 
+    let [flag_x, flag_y] = metadata
+        .finish_flag
+        .unwrap_or([metadata.finish_x + 200., metadata.spawn_point[1]]);
     let transform = Transform {
         scale: Vec3::ONE * scale,
-        translation: Vec3::new(14600., -500., 60.),
+        translation: Vec3::new(flag_x, flag_y, 60.),
         ..Default::default()
     };
     let sprite = SpriteBundle {
@@ -34,14 +144,30 @@ fn spawn_finish_flag(
         ..Default::default()
     };
 
-    cmd.spawn_bundle(sprite);
+    cmd.spawn_bundle(sprite)
+        .insert(Name::new("FinishFlag".to_owned()))
+        .insert(TrackMarkerComponent);
+
+    let mut builder = GeometryBuilder::new();
+    for x in &metadata.checkpoints {
+        let top = metadata.spawn_point[1] + 3000.;
+        let bottom = metadata.spawn_point[1] - 3000.;
+        builder.add(&shapes::Line(Vec2::new(*x, bottom), Vec2::new(*x, top)));
+    }
+    let checkpoint_lines = builder.build(
+        ShapeColors::new(Color::rgba(1., 1., 1., 0.3)),
+        DrawMode::Stroke(StrokeOptions::default().with_line_width(4.)),
+        Transform::from_translation(Vec3::new(0., 0., 45.)),
+    );
+    cmd.spawn_bundle(checkpoint_lines)
+        .insert(Name::new("Checkpoints".to_owned()))
+        .insert(TrackMarkerComponent);
 }
 
 fn spawn_meshes(
     mut cmd: Commands,
     mut ev_asset: EventReader<AssetEvent<Mesh>>,
     assets: ResMut<Assets<Mesh>>,
-    config: Res<RapierConfiguration>,
 ) {
     for ev in ev_asset.iter() {
         if let AssetEvent::Created { handle } = ev {
@@ -86,7 +212,7 @@ fn spawn_meshes(
                             triangles_2d.push((v1, v2, v3));
                         }
 
-                        build_terrain(&mut cmd, &triangles_2d, &config);
+                        cmd.insert_resource(GltfTriangles(triangles_2d));
                     }
                     _ => {
                         warn!("position wasn't a Float3");
@@ -97,11 +223,148 @@ fn spawn_meshes(
     }
 }
 
-fn build_terrain(
-    cmd: &mut Commands,
-    triangles: &[([f32; 2], [f32; 2], [f32; 2])],
-    config: &RapierConfiguration,
+//Key of everything the track set is loaded from, the aggregation settings don't need a reload.
+//The last entry is the generation of the evolved tracks, if coevolution is enabled.
+type TrackSetKey = (
+    TrackSource,
+    TrackGeneratorParams,
+    String,
+    bool,
+    bool,
+    u32,
+    String,
+    Option<usize>,
+);
+
+//Reloads the track set whenever it changes, and rebuilds the terrain whenever another track becomes active
+fn update_terrain(
+    mut cmd: Commands,
+    params: Res<SimulationParams>,
+    gltf: Option<Res<GltfTriangles>>,
+    config: Res<RapierConfiguration>,
+    query_terrain: Query<Entity, With<TerrainComponent>>,
+    mut tracks: ResMut<Tracks>,
+    mut metadata: ResMut<TrackMetadata>,
+    track_evolver: Res<TrackEvolver>,
+    mut built: Local<Option<TrackSetKey>>,
 ) {
+    let set = &params.track_set;
+    let wanted = (
+        params.track_source,
+        params.track_generator.clone(),
+        params.track_file.clone(),
+        set.enabled,
+        set.include_terrain_road,
+        set.generated_variants,
+        set.files.clone(),
+        Some(track_evolver.generation()).filter(|_| params.coevolution.enabled),
+    );
+    if built.as_ref() != Some(&wanted) {
+        let evolved = Some(&*track_evolver).filter(|_| params.coevolution.enabled);
+        match load_track_set(&params, gltf.as_deref(), evolved) {
+            None => return, //Mesh isn't loaded yet
+            Some(Ok(loaded)) => {
+                info!("loaded {} tracks", loaded.len());
+                tracks.tracks = loaded;
+                tracks.active = 0;
+                tracks.displayed = None;
+            }
+            Some(Err(e)) => error!("couldn't load track, keeping the old one: {}", e),
+        }
+        *built = Some(wanted); //Don't retry every frame
+    }
+
+    if tracks.is_displayed(tracks.active) {
+        return;
+    }
+    let track = match tracks.get(tracks.active) {
+        Some(track) => track.clone(),
+        None => return,
+    };
+
+    info!("building terrain for {}", track.name);
+    for e in query_terrain.iter() {
+        cmd.entity(e).despawn();
+    }
+    build_terrain(&mut cmd, &track.triangles.0, &config);
+    *metadata = track.metadata;
+
+    tracks.displayed = Some(tracks.active);
+}
+
+//Returns None if TerrainRoad is needed but its mesh isn't loaded yet.
+//Extra track files that fail to load are replaced by the main track, so the track indices stay the same.
+//Evolved tracks replace the whole track set.
+fn load_track_set(
+    params: &SimulationParams,
+    gltf: Option<&GltfTriangles>,
+    evolved: Option<&TrackEvolver>,
+) -> Option<Result<Vec<LoadedTrack>, String>> {
+    let set = &params.track_set;
+    let terrain_road = || {
+        let triangles = gltf.expect("TerrainRoad isn't loaded").0.clone();
+        LoadedTrack::new("TerrainRoad", triangles, TrackMetadata::default())
+    };
+    let generated = |generator: &TrackGeneratorParams| {
+        let (surface, metadata) = generate_track(generator);
+        LoadedTrack::from_surface(
+            format!("Procedural (seed {})", generator.seed),
+            surface,
+            metadata,
+        )
+    };
+
+    if let Some(evolver) = evolved {
+        return Some(Ok(evolver.population().iter().map(generated).collect()));
+    }
+
+    let needs_gltf = params.track_source == TrackSource::TerrainRoad
+        || (set.enabled && set.include_terrain_road);
+    if needs_gltf && gltf.is_none() {
+        return None;
+    }
+
+    let file = |path: &str| {
+        load_track(Path::new(path))
+            .map(|(surface, metadata)| LoadedTrack::from_surface(path, surface, metadata))
+    };
+
+    let primary = match params.track_source {
+        TrackSource::TerrainRoad => terrain_road(),
+        TrackSource::Procedural => generated(&params.track_generator),
+        TrackSource::File => match file(&params.track_file) {
+            Ok(track) => track,
+            Err(e) => return Some(Err(e)),
+        },
+    };
+
+    let mut tracks = vec![primary.clone()];
+    if set.enabled {
+        if set.include_terrain_road && params.track_source != TrackSource::TerrainRoad {
+            tracks.push(terrain_road());
+        }
+        for i in 1..=set.generated_variants {
+            let variant = TrackGeneratorParams {
+                seed: params.track_generator.seed.wrapping_add(i),
+                ..params.track_generator.clone()
+            };
+            tracks.push(generated(&variant));
+        }
+        for path in set.file_paths() {
+            match file(path) {
+                Ok(track) => tracks.push(track),
+                Err(e) => {
+                    error!("couldn't load track, using {} instead: {}", primary.name, e);
+                    tracks.push(primary.clone());
+                }
+            }
+        }
+    }
+
+    Some(Ok(tracks))
+}
+
+fn build_terrain(cmd: &mut Commands, triangles: &[Triangle], config: &RapierConfiguration) {
     let mut builder1 = GeometryBuilder::new();
     let mut builder2 = GeometryBuilder::new(); //can't clone a Builder :(
 
@@ -138,17 +401,7 @@ fn build_terrain(
         ..Default::default()
     };
 
-    let triangle_shapes = triangles
-        .iter()
-        .map(|(v1, v2, v3)| {
-            let a = Point2::new(v1[0], v1[1]) / config.scale;
-            let b = Point2::new(v2[0], v2[1]) / config.scale;
-            let c = Point2::new(v3[0], v3[1]) / config.scale;
-
-            (Isometry::identity(), ColliderShape::triangle(a, b, c))
-        })
-        .collect::<Vec<_>>();
-    let collider_shape = ColliderShape::compound(triangle_shapes);
+    let collider_shape = terrain_collider_shape(triangles, config.scale);
 
     let collider = ColliderBundle {
         shape: collider_shape,
@@ -162,7 +415,8 @@ fn build_terrain(
     ent.insert_bundle(collider)
         .insert_bundle(rigid_body)
         .insert(RigidBodyPositionSync::Discrete)
-        .insert(Name::new("Terrain".to_owned()));
+        .insert(Name::new("Terrain".to_owned()))
+        .insert(TerrainComponent);
 
     if debug_draw {
         ent.insert(ColliderDebugRender::with_id(1))
@@ -171,5 +425,6 @@ fn build_terrain(
 
     //Seperate entity for the outline
     cmd.spawn_bundle(shape_bundle_outline)
-        .insert(Name::new("TerrainOutlines".to_owned()));
+        .insert(Name::new("TerrainOutlines".to_owned()))
+        .insert(TerrainComponent);
 }
@@ -1,8 +1,9 @@
-use crate::{plugins::genetics::GlobalFitnessMap, vehicle::Vehicle};
+use crate::{fitness::GlobalFitnessMap, genetics_simulator::GeneticsSimulator};
 use crate::{
     plugins::genetics::SimulationParams,
-    vehicle::Block,
-    vehicle_states::{VehicleID, VehicleStates},
+    track::TrackMetadata,
+    vehicle::{block_physics, block_position, Block},
+    vehicle_states::{PoppedVehicle, VehicleID, VehicleStates, VehicleStatus},
 };
 use bevy::prelude::*;
 use bevy_rapier2d::physics::TimestepMode;
@@ -11,7 +12,12 @@ use ndarray::{Array, Array2};
 
 use log::{debug, error, info, trace, warn};
 
-use super::genetics::GeneticsGuiState; //IMPORTANT or you won't get any output during tests!
+use super::{
+    genetics::GeneticsGuiState,
+    obstacles::{spawn_obstacles, ObstacleComponent},
+    simulation_clock::{PlaybackState, SimulationClock},
+    terrain_mesh::Tracks,
+}; //IMPORTANT or you won't get any output during tests!
 
 pub struct VehicleSpawnerPlugin;
 
@@ -25,6 +31,7 @@ impl Plugin for VehicleSpawnerPlugin {
         app.add_startup_system_to_stage(StartupStage::PreStartup, setup_physics.system()); //TODO move this to another system?
 
         app.add_system(maybe_spawn_vehicle.system());
+        app.add_system(refill_free_slots.system());
         app.add_system(hide_unhovered_vehicles.system());
         //note: do NOT add the spawn_vehicle system to CoreStage::First or the physics break entirely
     }
@@ -72,28 +79,37 @@ fn hide_unhovered_vehicles(
 #[derive(new)]
 pub struct BlockComponent {
     pub belongs_to: VehicleID,
+    pub block: Block,
 }
 
 //TODO move this to its own plugin or main
 fn setup_physics(mut configuration: ResMut<RapierConfiguration>) {
     configuration.scale = 100.0; //pixels per meter
-    configuration.timestep_mode = TimestepMode::FixedTimestep; //TODO this seems to speed up the simulation when performance is fast
+    configuration.timestep_mode = TimestepMode::FixedTimestep; //Every step is exactly dt, the SimulationClock decides how many steps run per frame
 
     //(*configuration).physics_pipeline_active = false;
 }
 
 pub struct SpawnTimerState {
     pub timer: Timer,
+    running: Vec<RunningVehicle>, //Only used by the steady-state GA, where every vehicle has its own timer
 }
 
 impl Default for SpawnTimerState {
     fn default() -> Self {
         SpawnTimerState {
             timer: Timer::from_seconds(0.1, false), //small timer here so we have time to setup VehicleStates
+            running: vec![],
         }
     }
 }
 
+struct RunningVehicle {
+    id: VehicleID,
+    batch_slot: usize,
+    timer: Timer,
+}
+
 pub type VehicleIDs = Vec<VehicleID>;
 
 fn maybe_spawn_vehicle(
@@ -102,14 +118,32 @@ fn maybe_spawn_vehicle(
     mut materials: ResMut<Assets<ColorMaterial>>,
     configuration: Res<RapierConfiguration>,
     mut spawner_state: ResMut<SpawnTimerState>,
-    mut query_blocks: Query<Entity, With<BlockComponent>>,
-    time: Res<Time>,
+    mut query_blocks: Query<
+        (Entity, Option<&BlockComponent>, Option<&ObstacleComponent>),
+        Or<(With<BlockComponent>, With<ObstacleComponent>)>,
+    >,
+    clock: Res<SimulationClock>,
+    mut playback: ResMut<PlaybackState>,
     mut vehicle_states: ResMut<VehicleStates>,
     mut prev_vehicle_ids: ResMut<VehicleIDs>,
     fitness_map: ResMut<GlobalFitnessMap>,
     params: Res<SimulationParams>,
+    sim: Res<GeneticsSimulator>,
+    track: Res<TrackMetadata>,
+    mut tracks: ResMut<Tracks>,
 ) {
-    spawner_state.timer.tick(time.delta());
+    if params.steady_state.enabled && !params.uses_parallel_backend() {
+        return; //Vehicles are spawned one at a time by refill_free_slots
+    }
+    spawner_state.running.clear();
+
+    //Freeze the evaluation clock while paused, a single physics tick still advances it
+    if playback.paused && clock.delta().is_zero() {
+        spawner_state.timer.pause();
+    } else {
+        spawner_state.timer.unpause();
+    }
+    spawner_state.timer.tick(clock.delta()); //Measured in simulated time, so changing the simulation speed doesn't change the results
     if !spawner_state.timer.finished() {
         //Wait for timer to finish
         return;
@@ -117,50 +151,243 @@ fn maybe_spawn_vehicle(
 
     //finalize vehicle and remove them from VehicleIDs
     for id in prev_vehicle_ids.drain(..) {
-        info!("finalized vehicle {:?}", id);
-        let (vehicle, final_fitness) = vehicle_states.finalize_vehicle(id);
-        if fitness_map.insert(vehicle, final_fitness).is_some() {
-            warn!("vehicle override another's fitness, vehicle may have been simulated twice");
+        finalize_vehicle(id, &mut vehicle_states, &fitness_map, &sim);
+    }
+
+    if params.uses_parallel_backend() {
+        return; //Pending vehicles are handed to the parallel evaluator instead
+    }
+
+    //Vehicles can only be spawned on the track that is currently shown, so run all of them on one track before switching
+    if !tracks.is_displayed(tracks.active) {
+        return; //Wait for the terrain to be rebuilt
+    }
+    let pending_track = vehicle_states.next_pending_track();
+    if let Some(next) = pending_track.filter(|next| *next != tracks.active) {
+        let has_pending_on_active = vehicle_states
+            .get_vehicle_states()
+            .iter()
+            .any(|state| state.status == VehicleStatus::Pending && state.track == tracks.active);
+        if !has_pending_on_active {
+            info!("switching to track {}", tracks.name(next));
+            tracks.active = next;
+            return;
         }
     }
 
-    let popped_vehicles = vehicle_states.pop_vehicles(params.max_simultaneous_vehicles as usize);
+    if playback.pause_after_batch {
+        playback.pause(); //The next batch still gets spawned, but won't move
+    }
+
+    let popped_vehicles = vehicle_states.pop_vehicles(
+        params.max_simultaneous_vehicles as usize,
+        params.spawn_jitter,
+        Some(tracks.active),
+    );
     if !popped_vehicles.is_empty() {
         //vehicle spawned, set timer so we wait to simulate it
-        spawner_state.timer = Timer::from_seconds(params.max_generation_duration, false);
+        spawner_state.timer =
+            Timer::from_seconds(track.time_limit_or(params.max_generation_duration), false);
 
-        //cleanup any previous vehicles
-        for e in query_blocks.iter_mut() {
+        //cleanup any previous vehicles and their obstacles
+        for (e, _, _) in query_blocks.iter_mut() {
             commands.entity(e).despawn();
         }
 
         let vehicle_ids = popped_vehicles
             .iter()
-            .map(|(_, id, _)| *id)
+            .map(|popped| popped.id)
             .collect::<Vec<_>>();
         commands.insert_resource(vehicle_ids);
 
-        for (vehicle, vehicle_id, color) in popped_vehicles {
-            info!("popped and spawned vehicle [id={:?}]:", vehicle_id);
-
-            //let mut rng = rand::thread_rng();
-
-            let entities = setup_panels(
-                &vehicle,
-                vehicle_id,
-                color,
+        for popped in popped_vehicles {
+            spawn_vehicle(
+                &popped,
+                &track,
                 &configuration,
                 &asset_server,
                 &mut commands,
                 &mut materials,
             );
-            setup_joints(&mut commands, entities);
         }
     } else {
         warn!("ran out of vehicles");
     }
 }
 
+//Steady-state GA: every vehicle runs on its own timer, and as soon as one is done its batch slot gets the next
+//pending vehicle (e.g. a child bred by evolve_steady_state), instead of waiting for the whole batch to finish
+fn refill_free_slots(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    configuration: Res<RapierConfiguration>,
+    mut spawner_state: ResMut<SpawnTimerState>,
+    mut query_blocks: Query<
+        (Entity, Option<&BlockComponent>, Option<&ObstacleComponent>),
+        Or<(With<BlockComponent>, With<ObstacleComponent>)>,
+    >,
+    clock: Res<SimulationClock>,
+    mut playback: ResMut<PlaybackState>,
+    mut vehicle_states: ResMut<VehicleStates>,
+    mut vehicle_ids: ResMut<VehicleIDs>,
+    fitness_map: Res<GlobalFitnessMap>,
+    params: Res<SimulationParams>,
+    sim: Res<GeneticsSimulator>,
+    track: Res<TrackMetadata>,
+    mut tracks: ResMut<Tracks>,
+) {
+    if !params.steady_state.enabled || params.uses_parallel_backend() {
+        return; //maybe_spawn_vehicle spawns whole batches
+    }
+    let spawner_state = &mut *spawner_state;
+
+    //Vehicles of a batch that was spawned before steady-state was switched on keep the batch timer.
+    //Their IDs are in the order they were popped in, so the index is their batch slot.
+    for (batch_slot, id) in vehicle_ids.iter().enumerate() {
+        if !spawner_state
+            .running
+            .iter()
+            .any(|running| running.id == *id)
+        {
+            let timer = spawner_state.timer.clone();
+            spawner_state.running.push(RunningVehicle {
+                id: *id,
+                batch_slot,
+                timer,
+            });
+        }
+    }
+
+    for running in &mut spawner_state.running {
+        running.timer.tick(clock.delta()); //Simulated time, see maybe_spawn_vehicle
+    }
+    let (finished, running): (Vec<_>, Vec<_>) = spawner_state
+        .running
+        .drain(..)
+        .partition(|running| running.timer.finished());
+    spawner_state.running = running;
+
+    for done in &finished {
+        finalize_vehicle(done.id, &mut vehicle_states, &fitness_map, &sim);
+        vehicle_ids.retain(|id| *id != done.id);
+        for (e, block, obstacle) in query_blocks.iter_mut() {
+            let owner = block
+                .map(|b| b.belongs_to)
+                .or_else(|| obstacle.map(|o| o.belongs_to));
+            if owner == Some(done.id) {
+                commands.entity(e).despawn();
+            }
+        }
+    }
+    if !finished.is_empty() && playback.pause_after_batch {
+        playback.pause(); //The free slots still get refilled, but won't move
+    }
+
+    //The track can only be switched once nothing is running on it anymore
+    if !tracks.is_displayed(tracks.active) {
+        return; //Wait for the terrain to be rebuilt
+    }
+    let has_pending_on_active = vehicle_states
+        .get_vehicle_states()
+        .iter()
+        .any(|state| state.status == VehicleStatus::Pending && state.track == tracks.active);
+    if !has_pending_on_active {
+        if let Some(next) = vehicle_states.next_pending_track() {
+            if spawner_state.running.is_empty() {
+                info!("switching to track {}", tracks.name(next));
+                tracks.active = next;
+            }
+        }
+        return;
+    }
+
+    let batch_size = params.max_simultaneous_vehicles as usize;
+    let free_slots = (0..batch_size)
+        .filter(|slot| {
+            !spawner_state
+                .running
+                .iter()
+                .any(|running| running.batch_slot == *slot)
+        })
+        .collect::<Vec<_>>();
+    let popped_vehicles = vehicle_states.pop_into_slots(
+        &free_slots,
+        batch_size,
+        params.spawn_jitter,
+        Some(tracks.active),
+    );
+    for popped in popped_vehicles {
+        spawn_vehicle(
+            &popped,
+            &track,
+            &configuration,
+            &asset_server,
+            &mut commands,
+            &mut materials,
+        );
+        vehicle_ids.push(popped.id);
+        spawner_state.running.push(RunningVehicle {
+            id: popped.id,
+            batch_slot: popped.batch_slot,
+            timer: Timer::from_seconds(track.time_limit_or(params.max_generation_duration), false),
+        });
+    }
+
+    //The time shown in the GUI is the one of the vehicle that finishes next
+    if let Some(next) = spawner_state
+        .running
+        .iter()
+        .max_by_key(|running| running.timer.elapsed())
+    {
+        spawner_state.timer = next.timer.clone();
+    }
+}
+
+fn finalize_vehicle(
+    id: VehicleID,
+    vehicle_states: &mut VehicleStates,
+    fitness_map: &GlobalFitnessMap,
+    sim: &GeneticsSimulator,
+) {
+    info!("finalized vehicle {:?}", id);
+    let (vehicle, track, final_fitness, behavior) = vehicle_states.finalize_vehicle(id);
+    let mut record = fitness_map.entry(vehicle).or_default();
+    record.push(track, final_fitness, sim.generation());
+    record.push_behavior(&behavior);
+}
+
+fn spawn_vehicle(
+    popped: &PoppedVehicle,
+    track: &TrackMetadata,
+    configuration: &RapierConfiguration,
+    asset_server: &AssetServer,
+    commands: &mut Commands,
+    materials: &mut Assets<ColorMaterial>,
+) {
+    info!("popped and spawned vehicle [id={:?}]:", popped.id);
+
+    let entities = setup_panels(
+        popped,
+        track,
+        configuration,
+        asset_server,
+        commands,
+        materials,
+    );
+    setup_joints(commands, entities);
+
+    //Fresh obstacles for every vehicle, so every vehicle meets them in the same state
+    spawn_obstacles(
+        commands,
+        &track.obstacles,
+        popped.id,
+        collider_group(popped),
+        vehicle_color(popped),
+        configuration.scale,
+    );
+}
+
 #[derive(new, Clone)]
 struct EntityCell {
     pub ent: Entity,
@@ -168,60 +395,59 @@ struct EntityCell {
     pub block_type: Block,
 }
 
+fn vehicle_color(popped: &PoppedVehicle) -> Color {
+    let [r, g, b] = popped.color;
+    Color::rgb(r, g, b)
+}
+
+//Vehicles in the same batch may not collide with each other, so each gets its own collision group
+fn collider_group(popped: &PoppedVehicle) -> u32 {
+    let collider_group_index = popped.batch_slot;
+
+    assert!(collider_group_index < 32);
+    1 << collider_group_index
+}
+
 fn setup_panels(
-    vehicle: &Vehicle,
-    vehicle_id: VehicleID,
-    color: Color,
+    popped: &PoppedVehicle,
+    track: &TrackMetadata,
     configuration: &RapierConfiguration,
     asset_server: &AssetServer,
     cmd: &mut Commands,
     materials: &mut Assets<ColorMaterial>,
 ) -> Array2<Option<EntityCell>> {
-    let VehicleID(collider_group_index) = vehicle_id;
+    let vehicle = &popped.vehicle;
+    let vehicle_id = popped.id;
+    let color = vehicle_color(popped);
 
-    assert!(collider_group_index < 32);
-    let collider_group = 1 << collider_group_index;
+    let collider_group = collider_group(popped);
 
     let mut entities = Array::from_shape_simple_fn(vehicle.blocks.raw_dim(), || None);
 
-    let grid_cell_size = (60, 60); //how big every cell should be in pixels
-    let grid_size = vehicle.blocks.shape();
     let sim_scale = configuration.scale;
 
-    let panel_texture = (asset_server.load("textures/metalPanel.png"), (100, 100));
-    let wheel_texture = (asset_server.load("textures/saw.png"), (128, 128));
+    let panel_texture: Handle<Texture> = asset_server.load("textures/metalPanel.png");
+    let wheel_texture: Handle<Texture> = asset_server.load("textures/saw.png");
 
-    let spawn_offset = Vec2::new(0.0, 0.0);
+    let spawn_offset = Vec2::new(
+        (track.spawn_point[0] + popped.spawn_offset) / sim_scale,
+        track.spawn_point[1] / sim_scale,
+    );
     for ((y, x), block) in vehicle.blocks.indexed_iter() {
-        let texture;
-        let collider_shape;
-        let scale;
-        let friction;
-        match block {
-            Block::Air => continue,
-            Block::Panel => {
-                texture = panel_texture.clone();
-                let texture_size = texture.1;
-
-                scale = grid_cell_size.0 as f32 / texture_size.0 as f32;
-                collider_shape = ColliderShape::ball(scale / 2.); //TODO bug in rapier, can't use cubes here
-
-                friction = 0.1;
-            }
-            Block::Wheel => {
-                texture = wheel_texture.clone();
-                let texture_size = texture.1;
-
-                scale = grid_cell_size.0 as f32 / texture_size.0 as f32;
-                collider_shape = ColliderShape::ball(scale / 2.);
+        let physics = match block_physics(block) {
+            Some(physics) => physics,
+            None => continue, //Air
+        };
+        let texture = match block {
+            Block::Wheel => wheel_texture.clone(),
+            _ => panel_texture.clone(),
+        };
+        let scale = physics.scale;
+        let collider_shape = ColliderShape::ball(physics.radius); //TODO bug in rapier, can't use cubes here
+        let friction = physics.friction;
 
-                friction = 0.6;
-            }
-        }
-        let pos = Vec2::new(
-            (x as f32 - grid_size[0] as f32 / 2.) * (grid_cell_size.0 as f32) / sim_scale,
-            -(y as f32 - grid_size[1] as f32 / 2.) * (grid_cell_size.1 as f32) / sim_scale,
-        ) + spawn_offset;
+        let pos =
+            Vec2::from(block_position(x, y, vehicle.blocks.shape(), sim_scale)) + spawn_offset;
 
         let rigid_body = RigidBodyBundle {
             position: pos.into(),
@@ -239,7 +465,7 @@ fn setup_panels(
         };
 
         let material = materials.add(ColorMaterial {
-            texture: texture.0.into(),
+            texture: texture.into(),
             color,
         }); //NOTE - every vehicle needs its own material, right? Otherwise hovering doesn't work
 
@@ -256,7 +482,7 @@ fn setup_panels(
             .insert_bundle(sprite)
             .insert(RigidBodyPositionSync::Discrete)
             .insert(Name::new(format!("Block @ {}, {}", x, y)))
-            .insert(BlockComponent::new(vehicle_id))
+            .insert(BlockComponent::new(vehicle_id, *block))
             .id();
 
         entities[(y, x)] = Some(EntityCell::new(entity, pos, *block));
@@ -0,0 +1,339 @@
+use crate::{
+    genealogy::{self, Genealogy},
+    genetics_simulator::Pop,
+    genome::Genome,
+};
+#[cfg(feature = "app")]
+use bevy_inspector_egui::Inspectable;
+use rand::{
+    seq::{IteratorRandom, SliceRandom},
+    Rng,
+};
+
+use log::{debug, error, info, trace, warn}; //IMPORTANT or you won't get any output during tests!
+
+//Used by Niching::Speciation (like NEAT): the population is split into species of similar genomes,
+//which mostly breed among themselves and get offspring according to how well the species does as a whole
+#[cfg_attr(feature = "app", derive(Inspectable))]
+#[derive(Clone, Debug)]
+pub struct SpeciationParams {
+    #[cfg_attr(feature = "app", inspectable(min = 1., max = 48.))]
+    pub threshold: f32, //Genes, genomes closer than this to a species' representative belong to it
+
+    #[cfg_attr(feature = "app", inspectable(min = 0, max = 20))]
+    pub protected_generations: u32, //New species get double the offspring while they're this young
+
+    #[cfg_attr(feature = "app", inspectable(min = 0., max = 1.))]
+    pub interspecies_rate: f32, //Chance that the second parent comes from any species
+
+    #[cfg_attr(feature = "app", inspectable(min = 2, max = 24))]
+    pub elitism_size: u32, //Species with at least this many members keep their best genome unchanged
+}
+
+impl Default for SpeciationParams {
+    fn default() -> Self {
+        SpeciationParams {
+            threshold: 12.,
+            protected_generations: 5,
+            interspecies_rate: 0.05,
+            elitism_size: 5,
+        }
+    }
+}
+
+pub struct Species<G: Genome> {
+    pub id: usize,
+    pub representative: G,
+    pub created: usize,            //Generation the species appeared in
+    pub members: Vec<usize>,       //Indices in the population it was last formed from
+    pub best_fitness: Option<i64>, //Best fitness any member ever had
+}
+
+#[derive(Clone, Debug)]
+pub struct SpeciesStatistics {
+    pub id: usize,
+    pub size: usize,
+    pub avg_fitness: f64,
+    pub max_fitness: f64,
+}
+
+pub struct Speciation<G: Genome> {
+    species: Vec<Species<G>>,
+    next_id: usize,
+    statistics: Vec<Vec<SpeciesStatistics>>, //Every species that existed in every generation
+}
+
+impl<G: Genome> Default for Speciation<G> {
+    fn default() -> Self {
+        Speciation {
+            species: vec![],
+            next_id: 0,
+            statistics: vec![],
+        }
+    }
+}
+
+impl<G: Genome> Speciation<G> {
+    pub fn species(&self) -> &[Species<G>] {
+        &self.species
+    }
+
+    pub fn get_generational_statistics(&self) -> &Vec<Vec<SpeciesStatistics>> {
+        &self.statistics
+    }
+
+    //Species a genome would be assigned to, e.g. for genomes that weren't evaluated yet
+    pub fn classify(&self, genome: &G, params: &SpeciationParams) -> Option<usize> {
+        self.species
+            .iter()
+            .find(|s| genome.distance(&s.representative) < params.threshold as f64)
+            .map(|s| s.id)
+    }
+
+    //Assigns every genome to the first species whose representative is close enough, or to a new species.
+    //Species without members die out. Every genome needs a fitness.
+    pub fn speciate(&mut self, population: &Pop<G>, params: &SpeciationParams, generation: usize) {
+        for species in &mut self.species {
+            species.members.clear();
+        }
+
+        for (i, (genome, fitness)) in population.iter().enumerate() {
+            let threshold = params.threshold as f64;
+            let index = match self
+                .species
+                .iter()
+                .position(|s| genome.distance(&s.representative) < threshold)
+            {
+                Some(index) => index,
+                None => {
+                    self.species.push(Species {
+                        id: self.next_id,
+                        representative: genome.clone(),
+                        created: generation,
+                        members: vec![],
+                        best_fitness: None,
+                    });
+                    self.next_id += 1;
+                    self.species.len() - 1
+                }
+            };
+            let species = &mut self.species[index];
+            species.members.push(i);
+            species.best_fitness = species.best_fitness.max(*fitness);
+        }
+
+        let before = self.species.len();
+        self.species.retain(|s| !s.members.is_empty());
+        if self.species.len() < before {
+            info!("{} species died out", before - self.species.len());
+        }
+
+        //Random members represent the species in the next generation, like in NEAT
+        let mut rng = rand::thread_rng();
+        for species in &mut self.species {
+            let representative = *species.members.choose(&mut rng).unwrap();
+            species.representative = population[representative].0.clone();
+        }
+
+        let stats = self
+            .species
+            .iter()
+            .map(|s| {
+                let fitness = s
+                    .members
+                    .iter()
+                    .map(|i| population[*i].1.expect("missing fitness") as f64)
+                    .collect::<Vec<_>>();
+                SpeciesStatistics {
+                    id: s.id,
+                    size: s.members.len(),
+                    avg_fitness: fitness.iter().sum::<f64>() / fitness.len() as f64,
+                    max_fitness: fitness.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+                }
+            })
+            .collect();
+        self.statistics.push(stats);
+    }
+
+    //Makes the next population out of the species formed by `speciate`. Every species gets offspring according to
+    //its total fitness divided by its size (explicit fitness sharing), young species get a head start.
+    pub fn offspring(
+        &self,
+        population: &Pop<G>,
+        params: &SpeciationParams,
+        tournament_k: usize,
+        mutation_amount: usize,
+        generation: usize,
+        genealogy: &mut Genealogy<G>,
+    ) -> Pop<G> {
+        let mut rng = rand::thread_rng();
+        let min = population
+            .iter()
+            .map(|(_, f)| f.expect("missing fitness"))
+            .min()
+            .unwrap_or(0);
+        let fitness = |i: usize| population[i].1.unwrap() - min + 1; //Positive, so the shares add up
+
+        let shares = self
+            .species
+            .iter()
+            .map(|s| {
+                let mean = s.members.iter().map(|i| fitness(*i)).sum::<i64>() as f64
+                    / s.members.len() as f64;
+                if generation.saturating_sub(s.created) < params.protected_generations as usize {
+                    mean * 2.
+                } else {
+                    mean
+                }
+            })
+            .collect::<Vec<_>>();
+        let counts = apportion(&shares, population.len());
+
+        let tournament = |members: &[usize], rng: &mut rand::rngs::ThreadRng| {
+            let k = tournament_k.min(members.len()).max(1);
+            *members
+                .iter()
+                .choose_multiple(rng, k)
+                .into_iter()
+                .max_by_key(|i| fitness(**i))
+                .unwrap()
+        };
+        let everyone = (0..population.len()).collect::<Vec<_>>();
+
+        let mut next = vec![];
+        for (species, count) in self.species.iter().zip(counts) {
+            if count == 0 {
+                continue;
+            }
+
+            let mut count = count;
+            if species.members.len() >= params.elitism_size as usize {
+                let best = *species.members.iter().max_by_key(|i| fitness(**i)).unwrap();
+                next.push(population[best].clone()); //Keeps its fitness, it doesn't need to be evaluated again
+                count -= 1;
+            }
+
+            for _ in 0..count {
+                let a = tournament(&species.members, &mut rng);
+                let b = if rng.gen_bool(params.interspecies_rate as f64) {
+                    tournament(&everyone, &mut rng)
+                } else {
+                    tournament(&species.members, &mut rng)
+                };
+
+                let (brother, sister) =
+                    genealogy::breed(&population[a].0, &population[b].0, mutation_amount);
+                let birth = if rng.gen() { brother } else { sister };
+                genealogy.record_birth(&birth, generation);
+                next.push((birth.child, None));
+            }
+        }
+
+        info!(
+            "{} species, offspring: {:?}",
+            self.species.len(),
+            self.species
+                .iter()
+                .map(|s| (s.id, s.members.len()))
+                .collect::<Vec<_>>()
+        );
+
+        next
+    }
+}
+
+//Splits `total` into whole numbers proportional to the shares (largest remainder method)
+fn apportion(shares: &[f64], total: usize) -> Vec<usize> {
+    let sum = shares.iter().sum::<f64>();
+    if sum <= 0. {
+        let mut counts = vec![total / shares.len().max(1); shares.len()];
+        for count in counts.iter_mut().take(total % shares.len().max(1)) {
+            *count += 1;
+        }
+        return counts;
+    }
+
+    let exact = shares
+        .iter()
+        .map(|s| s / sum * total as f64)
+        .collect::<Vec<_>>();
+    let mut counts = exact.iter().map(|x| x.floor() as usize).collect::<Vec<_>>();
+
+    let mut by_remainder = (0..shares.len()).collect::<Vec<_>>();
+    by_remainder.sort_by(|a, b| {
+        let (ra, rb) = (exact[*a].fract(), exact[*b].fract());
+        rb.partial_cmp(&ra).unwrap()
+    });
+    let missing = total - counts.iter().sum::<usize>();
+    for i in by_remainder.into_iter().take(missing) {
+        counts[i] += 1;
+    }
+
+    counts
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use crate::vehicle::{Block, Vehicle};
+
+    let wheels = Vehicle::new_fill_with(Block::Wheel);
+    let panels = Vehicle::new_fill_with(Block::Panel);
+    let air = Vehicle::new_fill_with(Block::Air);
+    let mut params = SpeciationParams {
+        interspecies_rate: 0.,
+        ..SpeciationParams::default()
+    };
+
+    //Genomes further apart than the threshold form separate species
+    let mut speciation = Speciation::default();
+    let mut population = vec![(wheels.clone(), Some(10)); 4];
+    population.extend(vec![(panels.clone(), Some(10)); 2]);
+    speciation.speciate(&population, &params, 0);
+    let members = |s: &Speciation<Vehicle>| {
+        s.species()
+            .iter()
+            .map(|s| (s.id, s.members.len()))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(members(&speciation), vec![(0, 4), (1, 2)]);
+    assert_eq!(speciation.classify(&wheels, &params), Some(0));
+    assert_eq!(speciation.classify(&air, &params), None);
+
+    //Species without members die out, new ones get a new id
+    population.truncate(4);
+    population.extend(vec![(air.clone(), Some(10)); 2]);
+    speciation.speciate(&population, &params, 10);
+    assert_eq!(members(&speciation), vec![(0, 4), (2, 2)]);
+    //Every genome is counted in exactly one species
+    let statistics = speciation.get_generational_statistics();
+    assert_eq!(statistics.len(), 2);
+    let sizes = statistics[1]
+        .iter()
+        .map(|s| (s.id, s.size))
+        .collect::<Vec<_>>();
+    assert_eq!(sizes, vec![(0, 4), (2, 2)]);
+
+    //Without mutation, every species breeds copies of itself, so the offspring can be counted by genome
+    let count =
+        |next: &Pop<Vehicle>, genome: &Vehicle| next.iter().filter(|(g, _)| g == genome).count();
+    let mut genealogy = Genealogy::default();
+
+    //Equal fitness, but the new species is protected, so it gets twice the share
+    let next = speciation.offspring(&population, &params, 2, 0, 10, &mut genealogy);
+    assert_eq!((count(&next, &wheels), count(&next, &air)), (2, 4));
+    assert!(next.iter().all(|(_, f)| f.is_none()));
+
+    //Once it isn't protected anymore, both get the same share since their mean fitness is the same.
+    //The big species keeps its best member, fitness included.
+    params.protected_generations = 0;
+    params.elitism_size = 4;
+    let next = speciation.offspring(&population, &params, 2, 0, 10, &mut genealogy);
+    assert_eq!((count(&next, &wheels), count(&next, &air)), (3, 3));
+    assert_eq!(next[0], (wheels, Some(10)));
+    assert_eq!(next.iter().filter(|(_, f)| f.is_some()).count(), 1);
+
+    assert_eq!(apportion(&[1., 1., 2.], 7), vec![2, 2, 3]);
+    assert_eq!(apportion(&[0., 0.], 5), vec![3, 2]);
+    assert_eq!(apportion(&[3., 1.], 24).iter().sum::<usize>(), 24);
+}
@@ -0,0 +1,108 @@
+use crate::{genetics_simulator::Pop, genome::Genome};
+#[cfg(feature = "app")]
+use bevy_inspector_egui::Inspectable;
+
+use log::{debug, error, info, trace, warn}; //IMPORTANT or you won't get any output during tests!
+
+//Steady-state GA: instead of replacing the whole population once every vehicle is done, offspring are bred
+//whenever vehicles finish, and every evaluated child replaces the worst vehicle of the population
+#[cfg_attr(feature = "app", derive(Inspectable))]
+#[derive(Clone, Debug)]
+pub struct SteadyStateParams {
+    pub enabled: bool,
+
+    #[cfg_attr(feature = "app", inspectable(min = 1, max = 2))]
+    pub offspring: u32, //Children bred at once, from the same two parents
+
+    #[cfg_attr(feature = "app", inspectable(min = 1, max = 200))]
+    pub report_interval: u32, //Evaluations per entry in the statistics, which take the place of generations
+}
+
+impl Default for SteadyStateParams {
+    fn default() -> Self {
+        SteadyStateParams {
+            enabled: false,
+            offspring: 2,
+            report_interval: 24,
+        }
+    }
+}
+
+//Index of the evaluated genome with the lowest fitness, None if nothing was evaluated yet
+pub fn worst_evaluated<G: Genome>(population: &Pop<G>) -> Option<usize> {
+    population
+        .iter()
+        .enumerate()
+        .filter_map(|(i, (_, fitness))| fitness.map(|f| (i, f)))
+        .min_by_key(|(_, f)| *f)
+        .map(|(i, _)| i)
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use crate::{
+        fitness::GlobalFitnessMap,
+        genetics_simulator::{EvolutionParams, GeneticsSimulator},
+        vehicle::{Block, Vehicle},
+    };
+
+    let designs = [Block::Wheel, Block::Panel, Block::Air]
+        .iter()
+        .map(|b| Vehicle::new_fill_with(*b))
+        .chain(std::iter::once(Vehicle::from(
+            (0..48)
+                .map(|i| [Block::Wheel, Block::Air][i / 24])
+                .collect(),
+        )))
+        .collect::<Vec<_>>();
+    let population = designs
+        .iter()
+        .zip(&[Some(40), None, Some(10), Some(20)])
+        .map(|(design, fitness)| (design.clone(), *fitness))
+        .collect::<Pop<Vehicle>>();
+    assert_eq!(worst_evaluated(&population), Some(2));
+    assert_eq!(worst_evaluated(&vec![(designs[0].clone(), None)]), None);
+
+    let map: GlobalFitnessMap = std::sync::Arc::new(dashmap::DashMap::new());
+    for (design, fitness) in designs.iter().zip(&[40, 30, 10, 20]) {
+        map.entry(design.clone()).or_default().push(0, *fitness, 0);
+    }
+    let params = EvolutionParams {
+        steady_state: SteadyStateParams {
+            enabled: true,
+            offspring: 2,
+            report_interval: 2,
+        },
+        ..EvolutionParams::default()
+    };
+    let mut sim = GeneticsSimulator::from_population(designs.clone());
+
+    //Nothing is bred until the whole initial population is evaluated
+    assert!(sim
+        .steady_state_step(&designs[..2], &map, &params)
+        .is_empty());
+    assert_eq!(sim.get_generational_statistics().len(), 1);
+
+    //Injected genomes are handed out for evaluation like offspring. Stripes can't be bred from the designs.
+    let injected = Vehicle::from(
+        (0..48)
+            .map(|i| [Block::Wheel, Block::Panel][i % 2])
+            .collect(),
+    );
+    sim.inject(injected.clone());
+    let next = sim.steady_state_step(&designs[2..], &map, &params);
+    assert_eq!(next.len(), 5); //A child for every one of the four evaluations
+    assert_eq!(next[0], injected);
+    assert!(!sim.get_population_genomes().contains(&injected));
+    assert_eq!(sim.get_generational_statistics().len(), 2);
+
+    //Once evaluated, it replaces the worst genome
+    map.entry(injected.clone()).or_default().push(0, 100, 0);
+    assert!(sim
+        .steady_state_step(std::slice::from_ref(&injected), &map, &params)
+        .is_empty());
+    assert_eq!(sim.get_population().len(), 4);
+    assert!(sim.get_population().contains(&(injected, Some(100))));
+    assert!(!sim.get_population_genomes().contains(&designs[2]));
+}
@@ -0,0 +1,318 @@
+use crate::{
+    genome::Genome,
+    track::{TrackMetadata, TrackSurface},
+};
+#[cfg(feature = "app")]
+use bevy_inspector_egui::Inspectable;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::hash::{Hash, Hasher};
+
+use log::{debug, error, info, trace, warn}; //IMPORTANT or you won't get any output during tests!
+
+const SEGMENT_WIDTH: f32 = 50.; //Horizontal resolution of the heightfield, in pixels
+const START_X: f32 = -1500.;
+const FLAT_START_LENGTH: f32 = 2000.; //Flat area around the spawn point, so vehicles don't start on a hill
+const BASE_HEIGHT: f32 = -300.;
+const WALL_HEIGHT: f32 = 1500.; //Wall behind the spawn point so vehicles can't drive off the left edge
+const GAP_WIDTH: f32 = 250.;
+const MIN_PIECE_LENGTH: f32 = 3. * SEGMENT_WIDTH; //Between two gaps, so every gap splits the track
+
+#[cfg_attr(feature = "app", derive(Inspectable))]
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct TrackGeneratorParams {
+    #[cfg_attr(feature = "app", inspectable(min = 0, max = 10000))]
+    pub seed: u32,
+
+    #[cfg_attr(feature = "app", inspectable(min = 3000., max = 40000.))]
+    pub length: f32, //Pixels
+
+    #[cfg_attr(feature = "app", inspectable(min = 0., max = 3.))]
+    pub roughness: f32, //Small bumps
+
+    #[cfg_attr(feature = "app", inspectable(min = 0., max = 3.))]
+    pub hill_steepness: f32, //Big hills, these get steeper towards the end of the track
+
+    #[cfg_attr(feature = "app", inspectable(min = 0, max = 10))]
+    pub gaps: u32,
+
+    #[cfg_attr(feature = "app", inspectable(min = 0, max = 10))]
+    pub ramps: u32,
+}
+
+impl Default for TrackGeneratorParams {
+    fn default() -> Self {
+        TrackGeneratorParams {
+            seed: 0,
+            length: 15000.,
+            roughness: 1.0,
+            hill_steepness: 1.0,
+            gaps: 2,
+            ramps: 3,
+        }
+    }
+}
+
+//The parameters are never NaN, so they can be compared and hashed bit by bit
+impl Eq for TrackGeneratorParams {}
+
+impl Hash for TrackGeneratorParams {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.seed.hash(state);
+        self.length.to_bits().hash(state);
+        self.roughness.to_bits().hash(state);
+        self.hill_steepness.to_bits().hash(state);
+        self.gaps.hash(state);
+        self.ramps.hash(state);
+    }
+}
+
+//Used when tracks are evolved alongside the vehicles, the length always stays the same
+impl Genome for TrackGeneratorParams {
+    fn random() -> Self {
+        let mut rng = rand::thread_rng();
+        TrackGeneratorParams {
+            seed: rng.gen_range(0..=10000),
+            roughness: rng.gen_range(0.0..2.0),
+            hill_steepness: rng.gen_range(0.0..2.0),
+            gaps: rng.gen_range(0..=4),
+            ramps: rng.gen_range(0..=4),
+            ..TrackGeneratorParams::default()
+        }
+    }
+
+    //Every mutation nudges one of the parameters, or rerolls the seed
+    fn mutate(&mut self, amount: usize) {
+        let mut rng = rand::thread_rng();
+        for _ in 0..amount {
+            match rng.gen_range(0..5) {
+                0 => self.seed = rng.gen_range(0..=10000),
+                1 => self.roughness = (self.roughness + rng.gen_range(-0.3..0.3)).clamp(0., 3.),
+                2 => {
+                    self.hill_steepness =
+                        (self.hill_steepness + rng.gen_range(-0.3..0.3)).clamp(0., 3.)
+                }
+                3 => self.gaps = nudge_count(self.gaps, &mut rng),
+                _ => self.ramps = nudge_count(self.ramps, &mut rng),
+            }
+        }
+    }
+
+    //Every parameter comes from either parent
+    fn crossover(&self, other: &TrackGeneratorParams) -> (Self, Self) {
+        let mut rng = rand::thread_rng();
+        let (mut brother, mut sister) = (self.clone(), other.clone());
+        if rng.gen_bool(0.5) {
+            std::mem::swap(&mut brother.seed, &mut sister.seed);
+        }
+        if rng.gen_bool(0.5) {
+            std::mem::swap(&mut brother.roughness, &mut sister.roughness);
+        }
+        if rng.gen_bool(0.5) {
+            std::mem::swap(&mut brother.hill_steepness, &mut sister.hill_steepness);
+        }
+        if rng.gen_bool(0.5) {
+            std::mem::swap(&mut brother.gaps, &mut sister.gaps);
+        }
+        if rng.gen_bool(0.5) {
+            std::mem::swap(&mut brother.ramps, &mut sister.ramps);
+        }
+        (brother, sister)
+    }
+
+    //Rounded, so tiny differences in the parameters don't count as different genes
+    fn genes(&self) -> Vec<u32> {
+        vec![
+            self.seed,
+            (self.roughness * 10.).round() as u32,
+            (self.hill_steepness * 10.).round() as u32,
+            self.gaps,
+            self.ramps,
+        ]
+    }
+}
+
+impl TrackGeneratorParams {
+    pub fn summary(&self) -> String {
+        format!(
+            "seed {} rough {:.1} hills {:.1} gaps {} ramps {}",
+            self.seed, self.roughness, self.hill_steepness, self.gaps, self.ramps
+        )
+    }
+}
+
+fn nudge_count(count: u32, rng: &mut impl Rng) -> u32 {
+    (count as i32 + rng.gen_range(-1..=1)).clamp(0, 10) as u32
+}
+
+//Smooth 1D noise: random values on a lattice, cosine interpolated in between
+struct ValueNoise {
+    lattice: Vec<f32>,
+    wavelength: f32,
+}
+
+impl ValueNoise {
+    fn new(rng: &mut StdRng, length: f32, wavelength: f32) -> Self {
+        let points = (length / wavelength).ceil() as usize + 2;
+        let lattice = (0..points).map(|_| rng.gen_range(-1.0..1.0)).collect();
+        ValueNoise {
+            lattice,
+            wavelength,
+        }
+    }
+
+    fn sample(&self, x: f32) -> f32 {
+        let t = (x / self.wavelength).max(0.);
+        let i = (t.floor() as usize).min(self.lattice.len() - 2);
+        let frac = t - i as f32;
+        let smooth = (1. - (frac * std::f32::consts::PI).cos()) / 2.;
+
+        self.lattice[i] * (1. - smooth) + self.lattice[i + 1] * smooth
+    }
+}
+
+//The surface of a generated track, split into pieces wherever there's a gap
+pub fn generate_surface(params: &TrackGeneratorParams) -> TrackSurface {
+    let mut rng = StdRng::seed_from_u64(params.seed as u64);
+
+    let bumps = ValueNoise::new(&mut rng, params.length, 250.);
+    let hills = ValueNoise::new(&mut rng, params.length, 1500.);
+
+    let course_length = params.length - FLAT_START_LENGTH;
+
+    //Gaps and ramps only appear in the second half, the first half is for learning to drive at all
+    let (features_start, features_end) = (
+        FLAT_START_LENGTH + 0.5 * course_length,
+        FLAT_START_LENGTH + 0.95 * course_length,
+    );
+
+    //Every gap gets its own slot, so gaps never overlap and there's always ground in between.
+    //Short tracks get fewer gaps if they don't all fit.
+    let gap_count = (params.gaps as usize)
+        .min(((features_end - features_start) / (GAP_WIDTH + MIN_PIECE_LENGTH)) as usize);
+    let slot = (features_end - features_start) / gap_count.max(1) as f32;
+    let gaps = (0..gap_count)
+        .map(|i| {
+            let free = slot - GAP_WIDTH - MIN_PIECE_LENGTH;
+            features_start + i as f32 * slot + rng.gen_range(0.0..=free)
+        })
+        .collect::<Vec<_>>();
+
+    //Ramps may overlap, they just add up
+    let mut ramps = (0..params.ramps)
+        .map(|_| rng.gen_range(features_start..features_end))
+        .collect::<Vec<_>>();
+    ramps.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let ramp_length = 500.;
+    let ramp_height = 200.;
+
+    let height = |x: f32| {
+        let progress = ((x - FLAT_START_LENGTH) / course_length).clamp(0., 1.);
+        let fade_in = (x / FLAT_START_LENGTH - 1.).clamp(0., 1.); //Smooth transition out of the flat start
+
+        let bump = bumps.sample(x) * 60. * params.roughness;
+        let hill = hills.sample(x) * 600. * params.hill_steepness * (0.3 + progress);
+
+        //Ramps rise linearly and end in a sudden drop
+        let ramp = ramps
+            .iter()
+            .filter(|ramp_x| x <= **ramp_x && x > **ramp_x - ramp_length)
+            .map(|ramp_x| (1. - (ramp_x - x) / ramp_length) * ramp_height)
+            .sum::<f32>();
+
+        BASE_HEIGHT + (bump + hill) * fade_in + ramp
+    };
+
+    let in_gap = |x: f32| gaps.iter().any(|gap_x| x > *gap_x && x < gap_x + GAP_WIDTH);
+
+    let mut pieces = vec![];
+    let mut piece = vec![
+        [START_X, BASE_HEIGHT + WALL_HEIGHT],
+        [START_X + SEGMENT_WIDTH / 2., BASE_HEIGHT],
+    ];
+
+    let segments = ((params.length - START_X) / SEGMENT_WIDTH) as usize;
+    for i in 1..=segments {
+        let x = START_X + i as f32 * SEGMENT_WIDTH;
+
+        if in_gap(x) {
+            if piece.len() >= 2 {
+                pieces.push(piece);
+            }
+            piece = vec![];
+        } else {
+            piece.push([x, height(x)]);
+        }
+    }
+    if piece.len() >= 2 {
+        pieces.push(piece);
+    }
+
+    info!(
+        "generated track with seed {}: {} pieces",
+        params.seed,
+        pieces.len()
+    );
+
+    pieces
+}
+
+pub fn generate_track(params: &TrackGeneratorParams) -> (TrackSurface, TrackMetadata) {
+    let surface = generate_surface(params);
+    let metadata = TrackMetadata::for_surface(&surface);
+    (surface, metadata)
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    use crate::track::surface_height_at;
+
+    let params = TrackGeneratorParams::default();
+    let surface = generate_surface(&params);
+
+    //Same seed, same track, no matter how often it's generated
+    assert_eq!(generate_surface(&params), surface);
+    let other_seed = TrackGeneratorParams {
+        seed: 1,
+        ..params.clone()
+    };
+    assert_ne!(generate_surface(&other_seed), surface);
+
+    //Every gap splits the track, every piece goes from left to right, and the gaps are as wide as they should be
+    for seed in 0..20 {
+        for gaps in [0, 2, 10] {
+            let params = TrackGeneratorParams {
+                seed,
+                gaps,
+                ..params.clone()
+            };
+            let surface = generate_surface(&params);
+            assert_eq!(surface.len(), gaps as usize + 1);
+            for piece in &surface {
+                assert!(piece.windows(2).all(|pair| pair[0][0] < pair[1][0]));
+            }
+            for pair in surface.windows(2) {
+                let width = pair[1][0][0] - pair[0].last().unwrap()[0];
+                assert!((GAP_WIDTH..=GAP_WIDTH + 2. * SEGMENT_WIDTH).contains(&width));
+            }
+        }
+    }
+
+    //Gaps that don't fit on a short track are left out
+    let short = TrackGeneratorParams {
+        length: 3000.,
+        gaps: 10,
+        ..params.clone()
+    };
+    assert_eq!(generate_surface(&short).len(), 2);
+
+    //The flat start is the same for every seed, so vehicles always spawn on the ground
+    let (_, metadata) = generate_track(&other_seed);
+    assert_eq!(
+        surface_height_at(&surface, metadata.spawn_point[0]),
+        Some(BASE_HEIGHT)
+    );
+    assert!(metadata.finish_x < params.length);
+}
@@ -0,0 +1,447 @@
+use crate::track::{TrackMetadata, TrackSurface};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use log::{debug, error, info, trace, warn}; //IMPORTANT or you won't get any output during tests!
+
+const PNG_COLUMN_WIDTH: f32 = 50.; //Every column of pixels becomes this many pixels of track
+const PNG_MAX_HEIGHT: f32 = 1500.; //Height of a white pixel, black is 0
+const SVG_CURVE_SEGMENTS: usize = 16; //Bezier curves get flattened into this many lines
+
+//Track file in JSON format, see the README for an example
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TrackFile {
+    pub surface: SurfaceData,
+    #[serde(default)]
+    pub metadata: Option<TrackMetadata>, //Spawn point, finish line etc., sensible defaults are used if missing
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SurfaceData {
+    //Every piece is a polyline from left to right, pieces are separated by gaps
+    Polyline {
+        pieces: Vec<Vec<[f32; 2]>>,
+    },
+    //Evenly spaced heights, null means there's a gap
+    Heightfield {
+        start_x: f32,
+        spacing: f32,
+        heights: Vec<Option<f32>>,
+    },
+}
+
+impl SurfaceData {
+    pub fn to_surface(&self) -> TrackSurface {
+        match self {
+            SurfaceData::Polyline { pieces } => pieces.clone(),
+            SurfaceData::Heightfield {
+                start_x,
+                spacing,
+                heights,
+            } => heightfield_to_surface(*start_x, *spacing, heights),
+        }
+    }
+}
+
+//Picks the loader based on the file extension (.json, .png or .svg).
+//Only JSON files can contain metadata, the other formats get the defaults from TrackMetadata::for_surface.
+pub fn load_track(path: &Path) -> Result<(TrackSurface, TrackMetadata), String> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+
+    let (surface, metadata) = match extension.as_deref() {
+        Some("json") => load_json(path)?,
+        Some("png") => (load_heightmap_png(path)?, None),
+        Some("svg") => (load_svg_path(path)?, None),
+        _ => return Err(format!("unknown track format: {}", path.display())),
+    };
+
+    if surface.iter().all(|piece| piece.len() < 2) {
+        return Err(format!("track {} has no surface", path.display()));
+    }
+
+    info!(
+        "loaded track {} with {} pieces",
+        path.display(),
+        surface.len()
+    );
+    let metadata = match metadata {
+        Some(metadata) => metadata.with_flag_on(&surface),
+        None => TrackMetadata::for_surface(&surface),
+    };
+    Ok((surface, metadata))
+}
+
+pub fn save_track(path: &Path, file: &TrackFile) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(file)
+        .map_err(|e| format!("couldn't serialize track: {}", e))?;
+    std::fs::write(path, json).map_err(|e| format!("couldn't save {}: {}", path.display(), e))?;
+
+    info!("saved track to {}", path.display());
+    Ok(())
+}
+
+fn read_to_string(path: &Path) -> Result<String, String> {
+    std::fs::read_to_string(path).map_err(|e| format!("couldn't read {}: {}", path.display(), e))
+}
+
+fn load_json(path: &Path) -> Result<(TrackSurface, Option<TrackMetadata>), String> {
+    let file: TrackFile = serde_json::from_str(&read_to_string(path)?)
+        .map_err(|e| format!("invalid track file {}: {}", path.display(), e))?;
+    Ok((file.surface.to_surface(), file.metadata))
+}
+
+fn heightfield_to_surface(start_x: f32, spacing: f32, heights: &[Option<f32>]) -> TrackSurface {
+    let mut pieces = vec![];
+    let mut piece = vec![];
+
+    for (i, height) in heights.iter().enumerate() {
+        match height {
+            Some(y) => piece.push([start_x + i as f32 * spacing, *y]),
+            None => {
+                if piece.len() >= 2 {
+                    pieces.push(piece);
+                }
+                piece = vec![];
+            }
+        }
+    }
+    if piece.len() >= 2 {
+        pieces.push(piece);
+    }
+
+    pieces
+}
+
+//Every column of the image is one height sample: the brighter, the higher. Transparent columns are gaps.
+fn load_heightmap_png(path: &Path) -> Result<TrackSurface, String> {
+    let image = image::open(path)
+        .map_err(|e| format!("couldn't open {}: {}", path.display(), e))?
+        .into_luma_alpha8();
+
+    let (width, height) = image.dimensions();
+    let heights = (0..width)
+        .map(|x| {
+            let (mut brightness, mut alpha) = (0., 0.);
+            for y in 0..height {
+                let pixel = image.get_pixel(x, y);
+                brightness += pixel[0] as f32 / 255.;
+                alpha += pixel[1] as f32 / 255.;
+            }
+
+            if alpha / (height as f32) < 0.5 {
+                None
+            } else {
+                Some(brightness / height as f32 * PNG_MAX_HEIGHT)
+            }
+        })
+        .collect::<Vec<_>>();
+
+    //Start a bit left of the spawn point, like the other tracks
+    let start_x = -(width as f32 / 10.).floor() * PNG_COLUMN_WIDTH;
+    Ok(heightfield_to_surface(start_x, PNG_COLUMN_WIDTH, &heights))
+}
+
+//Uses the first <path> in the file. Every subpath becomes a piece of the track.
+fn load_svg_path(path: &Path) -> Result<TrackSurface, String> {
+    let svg = read_to_string(path)?;
+
+    let d = svg
+        .split("<path")
+        .nth(1)
+        .and_then(|tag| tag.split('>').next())
+        .and_then(|tag| attribute(tag, "d"))
+        .ok_or_else(|| format!("no <path d=\"...\"> found in {}", path.display()))?;
+
+    let mut pieces = parse_svg_path(d)?;
+
+    for piece in &mut pieces {
+        for point in piece.iter_mut() {
+            point[1] = -point[1]; //SVG has Y pointing down
+        }
+
+        //Pieces have to go from left to right
+        if piece.first().map(|p| p[0]) > piece.last().map(|p| p[0]) {
+            piece.reverse();
+        }
+    }
+
+    Ok(pieces)
+}
+
+//Value of an attribute in the inside of an XML tag. Attributes are separated by any whitespace and quoted with " or '.
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = tag;
+    while let Some(found) = rest.find(name) {
+        let preceded_by_whitespace = rest[..found].ends_with(char::is_whitespace);
+        rest = &rest[found + name.len()..];
+
+        let value = match rest.trim_start().strip_prefix('=') {
+            Some(value) if preceded_by_whitespace => value.trim_start(),
+            _ => continue, //Part of another name or value, e.g. data-d="..."
+        };
+        let quote = value.chars().next().filter(|q| *q == '"' || *q == '\'')?;
+        return value[1..].split(quote).next();
+    }
+    None
+}
+
+//Supports the M, L, H, V, C, Q and Z commands, both absolute and relative. Z ends a piece without closing it.
+fn parse_svg_path(d: &str) -> Result<TrackSurface, String> {
+    //Split into commands and numbers, e.g. "M10-5.5l3,4e-1" -> ["M", "10", "-5.5", "l", "3", "4e-1"]
+    let mut tokens = vec![];
+    let mut number = String::new();
+    for c in d.chars() {
+        //No path command uses e or E, after a number they are always an exponent
+        let exponent = (c == 'e' || c == 'E') && !number.is_empty();
+        let after_exponent = number.ends_with(['e', 'E']);
+        let starts_new_number = (c == '-' || c == '+') && !number.is_empty() && !after_exponent;
+        if c.is_ascii_alphabetic() && !exponent
+            || c == ','
+            || c.is_whitespace()
+            || starts_new_number
+        {
+            if !number.is_empty() {
+                tokens.push(std::mem::take(&mut number));
+            }
+            if c.is_ascii_alphabetic() {
+                tokens.push(c.to_string());
+            }
+            if starts_new_number {
+                number.push(c);
+            }
+        } else {
+            number.push(c);
+        }
+    }
+    if !number.is_empty() {
+        tokens.push(number);
+    }
+
+    let mut pieces: TrackSurface = vec![];
+    let mut piece: Vec<[f32; 2]> = vec![];
+    let mut current = [0f32, 0f32];
+    let mut start = current;
+    let mut command = ' ';
+
+    let mut i = 0;
+    let next_number = |i: &mut usize| -> Result<f32, String> {
+        let token = tokens
+            .get(*i)
+            .ok_or_else(|| "unexpected end of svg path".to_string())?;
+        *i += 1;
+        token
+            .parse::<f32>()
+            .map_err(|_| format!("invalid number in svg path: {}", token))
+    };
+
+    while i < tokens.len() {
+        if let Some(c) = tokens[i].chars().next().filter(|c| c.is_ascii_alphabetic()) {
+            command = c;
+            i += 1;
+        }
+
+        let relative = command.is_ascii_lowercase();
+        let offset = if relative { current } else { [0., 0.] };
+        let point = |i: &mut usize| -> Result<[f32; 2], String> {
+            Ok([next_number(i)? + offset[0], next_number(i)? + offset[1]])
+        };
+
+        match command.to_ascii_uppercase() {
+            'M' => {
+                if piece.len() >= 2 {
+                    pieces.push(std::mem::take(&mut piece));
+                }
+                current = point(&mut i)?;
+                start = current;
+                piece = vec![current];
+                command = if relative { 'l' } else { 'L' }; //Following pairs are implicit linetos
+            }
+            'L' => {
+                current = point(&mut i)?;
+                piece.push(current);
+            }
+            'H' => {
+                current[0] = next_number(&mut i)? + offset[0];
+                piece.push(current);
+            }
+            'V' => {
+                current[1] = next_number(&mut i)? + offset[1];
+                piece.push(current);
+            }
+            'C' | 'Q' => {
+                let cubic = command.to_ascii_uppercase() == 'C';
+                let control1 = point(&mut i)?;
+                let control2 = if cubic { point(&mut i)? } else { control1 };
+                let end = point(&mut i)?;
+                let from = current;
+
+                for step in 1..=SVG_CURVE_SEGMENTS {
+                    let t = step as f32 / SVG_CURVE_SEGMENTS as f32;
+                    let u = 1. - t;
+                    let p = (0..2)
+                        .map(|axis| {
+                            if cubic {
+                                u * u * u * from[axis]
+                                    + 3. * u * u * t * control1[axis]
+                                    + 3. * u * t * t * control2[axis]
+                                    + t * t * t * end[axis]
+                            } else {
+                                u * u * from[axis] + 2. * u * t * control1[axis] + t * t * end[axis]
+                            }
+                        })
+                        .collect::<Vec<_>>();
+                    piece.push([p[0], p[1]]);
+                }
+                current = end;
+            }
+            'Z' => {
+                //A track is a ground line, not a closed shape, so Z only ends the piece
+                if piece.len() >= 2 {
+                    pieces.push(std::mem::take(&mut piece));
+                }
+                current = start;
+                piece = vec![current]; //Drawing on without an M starts from here
+                command = ' '; //Z takes no numbers
+            }
+            other => return Err(format!("unsupported svg path command: {}", other)),
+        }
+    }
+
+    if piece.len() >= 2 {
+        pieces.push(piece);
+    }
+
+    Ok(pieces)
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    let dir = std::env::temp_dir().join(format!("tracks-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    //JSON polylines, metadata is optional
+    let json = dir.join("polyline.json");
+    std::fs::write(
+        &json,
+        r#"{"surface": {"type": "polyline", "pieces": [[[0, 0], [500, 50]], [[700, 0], [2000, 0]]]}}"#,
+    )
+    .unwrap();
+    let (surface, metadata) = load_track(&json).unwrap();
+    assert_eq!(
+        surface,
+        vec![vec![[0., 0.], [500., 50.]], vec![[700., 0.], [2000., 0.]]]
+    );
+    assert_eq!(metadata.finish_x, 1400.);
+    assert!(metadata.finish_flag.is_some());
+
+    //Saved tracks load again, metadata included
+    let file = TrackFile {
+        surface: SurfaceData::Heightfield {
+            start_x: -100.,
+            spacing: 100.,
+            heights: vec![
+                Some(0.),
+                Some(10.),
+                None,
+                Some(20.),
+                Some(30.),
+                Some(40.),
+                None,
+                Some(0.),
+            ],
+        },
+        metadata: Some(TrackMetadata {
+            time_limit: Some(12.),
+            ..metadata
+        }),
+    };
+    let saved = dir.join("heightfield.json");
+    save_track(&saved, &file).unwrap();
+    let (surface, loaded) = load_track(&saved).unwrap();
+    //Gaps split the heightfield, pieces with a single height are dropped
+    assert_eq!(
+        surface,
+        vec![
+            vec![[-100., 0.], [0., 10.]],
+            vec![[200., 20.], [300., 30.], [400., 40.]]
+        ]
+    );
+    assert_eq!(loaded.time_limit, Some(12.));
+
+    //PNG heightmaps: white is high, transparent columns are gaps
+    let png = dir.join("heightmap.png");
+    image::GrayAlphaImage::from_fn(20, 4, |x, _| match x {
+        5 => image::LumaA([0, 0]),
+        x => image::LumaA([if x < 5 { 0 } else { 255 }, 255]),
+    })
+    .save(&png)
+    .unwrap();
+    let (surface, _) = load_track(&png).unwrap();
+    assert_eq!(surface.len(), 2);
+    assert_eq!(surface[0].len(), 5);
+    assert_eq!(surface[0][0], [-2. * PNG_COLUMN_WIDTH, 0.]);
+    assert_eq!(surface[1][0], [4. * PNG_COLUMN_WIDTH, PNG_MAX_HEIGHT]);
+
+    //SVG paths, Y gets flipped and every piece goes from left to right
+    let svg = dir.join("path.svg");
+    std::fs::write(
+        &svg,
+        r#"<svg><path id="ground" d="M100,0 L0,0 M200 10 h100 v-10 l50,0"/></svg>"#,
+    )
+    .unwrap();
+    let (surface, _) = load_track(&svg).unwrap();
+    assert_eq!(
+        surface,
+        vec![
+            vec![[0., 0.], [100., 0.]],
+            vec![[200., -10.], [300., -10.], [300., 0.], [350., 0.]]
+        ]
+    );
+
+    //The d attribute can follow any whitespace and use either quote
+    std::fs::write(
+        &svg,
+        "<svg>\n  <path data-d=\"M0,0\"\n\td = 'M0,0 L100,0'\n  />\n</svg>",
+    )
+    .unwrap();
+    let (surface, _) = load_track(&svg).unwrap();
+    assert_eq!(surface, vec![vec![[0., 0.], [100., 0.]]]);
+
+    std::fs::write(&svg, r#"<svg><path id="d" stroke="black"/></svg>"#).unwrap();
+    assert!(load_track(&svg).is_err());
+
+    assert!(load_track(&dir.join("track.txt")).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    //Numbers can run into each other, curves get flattened
+    let pieces = parse_svg_path("M0-5.5L10,0Q15,5 20,0C25-5,30-5,40,0").unwrap();
+    assert_eq!(pieces[0][..2], [[0., -5.5], [10., 0.]]);
+    assert_eq!(pieces[0].len(), 2 + 2 * SVG_CURVE_SEGMENTS);
+    assert_eq!(*pieces[0].last().unwrap(), [40., 0.]);
+
+    //Z ends the piece, it doesn't close it. Drawing on starts a new piece at the start of the old one.
+    let pieces = parse_svg_path("m0,0 l100,0 l0,100 z l-50,0 M500,0 L600,0 Z").unwrap();
+    assert_eq!(
+        pieces,
+        vec![
+            vec![[0., 0.], [100., 0.], [100., 100.]],
+            vec![[0., 0.], [-50., 0.]],
+            vec![[500., 0.], [600., 0.]]
+        ]
+    );
+
+    //Numbers can have exponents
+    let pieces = parse_svg_path("M0,0 L1e2,-5E-1 l1e1-1 h+2E+1").unwrap();
+    assert_eq!(
+        pieces,
+        vec![vec![[0., 0.], [100., -0.5], [110., -1.5], [130., -1.5]]]
+    );
+
+    assert!(parse_svg_path("M0,0 A10,10 0 0 1 20,0").is_err());
+    assert!(parse_svg_path("M0,0 L10").is_err());
+}
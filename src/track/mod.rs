@@ -0,0 +1,240 @@
+#[cfg(feature = "app")]
+use bevy_inspector_egui::Inspectable;
+use serde::{Deserialize, Serialize};
+
+pub mod generator;
+pub mod loader;
+pub mod obstacles;
+
+use obstacles::Obstacle;
+
+#[cfg_attr(feature = "app", derive(Inspectable))]
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum TrackSource {
+    TerrainRoad, //models/TerrainRoad.glb
+    Procedural,  //Generated from SimulationParams::track_generator
+    File,        //Loaded from SimulationParams::track_file
+}
+
+//Scores every vehicle on several tracks, so evolution doesn't overfit to the bumps of a single one
+#[cfg_attr(feature = "app", derive(Inspectable))]
+#[derive(PartialEq, Clone, Debug)]
+pub struct TrackSet {
+    pub enabled: bool, //Off: only the track from track_source is used
+    pub include_terrain_road: bool,
+
+    #[cfg_attr(feature = "app", inspectable(min = 0, max = 8))]
+    pub generated_variants: u32, //track_generator with the seeds after its own
+
+    pub files: String, //Comma separated paths of extra track files
+    pub aggregation: TrackAggregation,
+    pub weights: String, //Comma separated, in track order, only used by TrackAggregation::Weighted. Missing weights are 1.
+}
+
+impl Default for TrackSet {
+    fn default() -> Self {
+        TrackSet {
+            enabled: false,
+            include_terrain_road: true,
+            generated_variants: 2,
+            files: "".to_owned(),
+            aggregation: TrackAggregation::Mean,
+            weights: "".to_owned(),
+        }
+    }
+}
+
+//How the fitness on every track is combined into a single fitness
+#[cfg_attr(feature = "app", derive(Inspectable))]
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum TrackAggregation {
+    Mean,
+    Min, //Only as good as the worst track
+    Weighted,
+}
+
+impl TrackSet {
+    pub fn file_paths(&self) -> Vec<&str> {
+        self.files
+            .split(',')
+            .map(|path| path.trim())
+            .filter(|path| !path.is_empty())
+            .collect()
+    }
+
+    //The track from track_source comes first, then TerrainRoad, then the generated variants, then the files
+    pub fn track_count(&self, primary: TrackSource) -> usize {
+        if !self.enabled {
+            return 1;
+        }
+        let terrain_road = self.include_terrain_road && primary != TrackSource::TerrainRoad;
+        1 + terrain_road as usize + self.generated_variants as usize + self.file_paths().len()
+    }
+
+    pub fn weight(&self, track: usize) -> f64 {
+        self.weights
+            .split(',')
+            .nth(track)
+            .and_then(|w| w.trim().parse().ok())
+            .unwrap_or(1.)
+    }
+
+    //Takes the fitness on every track as (track index, fitness)
+    pub fn aggregate(&self, per_track: &[(usize, i64)]) -> i64 {
+        match self.aggregation {
+            TrackAggregation::Mean => {
+                per_track.iter().map(|(_, f)| *f as f64).sum::<f64>() / per_track.len() as f64
+            }
+            TrackAggregation::Min => per_track
+                .iter()
+                .map(|(_, f)| *f as f64)
+                .fold(f64::INFINITY, f64::min),
+            TrackAggregation::Weighted => {
+                let total_weight = per_track.iter().map(|(t, _)| self.weight(*t)).sum::<f64>();
+                per_track
+                    .iter()
+                    .map(|(t, f)| self.weight(*t) * *f as f64)
+                    .sum::<f64>()
+                    / total_weight
+            }
+        }
+        .round() as i64
+    }
+}
+
+pub type Triangle = ([f32; 2], [f32; 2], [f32; 2]); //In pixels
+
+//Polylines going from left to right, with a gap between every two pieces
+pub type TrackSurface = Vec<Vec<[f32; 2]>>;
+
+//Where vehicles spawn and where they have to go, in pixels. Every track can define its own.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TrackMetadata {
+    pub spawn_point: [f32; 2], //Center of the vehicle grid, fitness is measured from here
+    #[serde(default = "default_spawn_area")]
+    pub spawn_area: [f32; 2], //Size of the spawn indicator box
+    pub finish_x: f32,
+    #[serde(default)]
+    pub finish_flag: Option<[f32; 2]>, //Where the flag sprite goes, placed on the surface behind the finish line if missing
+    #[serde(default)]
+    pub checkpoints: Vec<f32>, //X coordinates, from left to right
+    #[serde(default)]
+    pub time_limit: Option<f32>, //Simulated seconds, overrides SimulationParams::max_generation_duration
+    #[serde(default)]
+    pub obstacles: Vec<Obstacle>, //Every vehicle gets its own copy, so vehicles can't influence each other through them
+}
+
+fn default_spawn_area() -> [f32; 2] {
+    [600., 600.]
+}
+
+//Metadata of models/TerrainRoad.glb
+impl Default for TrackMetadata {
+    fn default() -> Self {
+        TrackMetadata {
+            spawn_point: [0., 0.],
+            spawn_area: default_spawn_area(),
+            finish_x: 14400.,
+            finish_flag: Some([14600., -500.]),
+            checkpoints: vec![3600., 7200., 10800.],
+            time_limit: None,
+            obstacles: vec![],
+        }
+    }
+}
+
+impl TrackMetadata {
+    //For tracks that don't come with metadata: spawn at the origin, finish near the right end
+    pub fn for_surface(surface: &TrackSurface) -> Self {
+        let right_end = surface
+            .iter()
+            .flatten()
+            .map(|[x, _]| *x)
+            .fold(f32::NEG_INFINITY, f32::max);
+        let finish_x = right_end - 600.;
+
+        TrackMetadata {
+            finish_x,
+            finish_flag: None,
+            checkpoints: (1..4).map(|i| finish_x * i as f32 / 4.).collect(),
+            ..Default::default()
+        }
+        .with_flag_on(surface)
+    }
+
+    //Puts the flag on the surface, unless the track already says where it goes
+    pub fn with_flag_on(mut self, surface: &TrackSurface) -> Self {
+        if self.finish_flag.is_none() {
+            let x = self.finish_x + 200.;
+            let y = surface_height_at(surface, x)
+                .or_else(|| surface_height_at(surface, self.finish_x))
+                .unwrap_or(self.spawn_point[1]);
+            self.finish_flag = Some([x, y + 150.]);
+        }
+        self
+    }
+
+    //How far a vehicle has to get from the spawn point to finish, comparable to fitness
+    pub fn finish_distance(&self) -> i64 {
+        (self.finish_x - self.spawn_point[0]).round() as i64
+    }
+
+    //How many checkpoints a vehicle with this fitness has passed
+    pub fn checkpoints_reached(&self, fitness: i64) -> usize {
+        self.checkpoints
+            .iter()
+            .filter(|x| fitness as f32 >= **x - self.spawn_point[0])
+            .count()
+    }
+
+    pub fn time_limit_or(&self, default: f32) -> f32 {
+        self.time_limit.unwrap_or(default)
+    }
+}
+
+//Height of the surface at x, or None if x is in a gap or outside of the track
+pub fn surface_height_at(surface: &TrackSurface, x: f32) -> Option<f32> {
+    surface.iter().find_map(|piece| {
+        piece.windows(2).find_map(|pair| {
+            let ([x1, y1], [x2, y2]) = (pair[0], pair[1]);
+            if x >= x1 && x <= x2 && x2 > x1 {
+                Some(y1 + (y2 - y1) * (x - x1) / (x2 - x1))
+            } else {
+                None
+            }
+        })
+    })
+}
+
+const FLOOR_DEPTH: f32 = 1500.; //How far the ground extends below the lowest point of the surface
+
+pub fn surface_to_triangles(surface: &TrackSurface) -> Vec<Triangle> {
+    let lowest = surface
+        .iter()
+        .flatten()
+        .map(|[_, y]| *y)
+        .fold(f32::INFINITY, f32::min);
+
+    surface
+        .iter()
+        .flat_map(|piece| polyline_to_triangles(piece, lowest - FLOOR_DEPTH))
+        .collect()
+}
+
+//Turns the surface of a track (a polyline going from left to right) into a solid strip of triangles.
+//Everything between the surface and floor_y is solid ground.
+pub fn polyline_to_triangles(surface: &[[f32; 2]], floor_y: f32) -> Vec<Triangle> {
+    let mut triangles = vec![];
+
+    for pair in surface.windows(2) {
+        let [x1, y1] = pair[0];
+        let [x2, y2] = pair[1];
+
+        let (bottom1, bottom2) = ([x1, floor_y], [x2, floor_y]);
+
+        triangles.push(([x1, y1], [x2, y2], bottom2));
+        triangles.push(([x1, y1], bottom2, bottom1));
+    }
+
+    triangles
+}
@@ -0,0 +1,182 @@
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+
+//Things on the track that move, in pixels. Defined in the track file, next to the spawn point and finish line.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Obstacle {
+    //Moves back and forth between position and position + travel, nothing can push it
+    MovingPlatform {
+        position: [f32; 2], //Center
+        size: [f32; 2],
+        travel: [f32; 2],
+        period: f32, //Simulated seconds for a round trip
+    },
+    //Heavy ball hanging from a fixed pivot
+    Pendulum {
+        pivot: [f32; 2],
+        length: f32,
+        radius: f32,
+        #[serde(default)]
+        start_angle: f32, //Degrees, 0 is straight down
+    },
+    //Plank balancing on a fixed pivot in its middle
+    Seesaw {
+        pivot: [f32; 2],
+        length: f32,
+        #[serde(default = "default_seesaw_thickness")]
+        thickness: f32,
+    },
+    //Ball that drops from where it starts, and rolls down the track from there
+    Boulder {
+        position: [f32; 2],
+        radius: f32,
+    },
+}
+
+fn default_seesaw_thickness() -> f32 {
+    30.
+}
+
+//Offset of a moving platform from its start position, `time` simulated seconds after it was spawned.
+//Eases in and out at both ends.
+pub fn platform_offset(travel: [f32; 2], period: f32, time: f32) -> [f32; 2] {
+    let t = (1. - (2. * PI * time / period.max(0.1)).cos()) / 2.;
+    [travel[0] * t, travel[1] * t]
+}
+
+//A single rigid body of an obstacle, in pixels
+pub struct ObstaclePart {
+    pub body: PartBody,
+    pub position: [f32; 2],
+    pub shape: PartShape,
+    pub density: f32,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PartBody {
+    Static,
+    Dynamic,
+    Kinematic, //Follows platform_offset, nothing can push it
+}
+
+pub enum PartShape {
+    Ball { radius: f32 },
+    Box { size: [f32; 2] },
+    Pivot, //Fixed point other parts hang from, doesn't collide
+}
+
+//Ball joint between two parts, anchors are relative to the parts
+pub struct ObstacleJoint {
+    pub a: usize,
+    pub b: usize,
+    pub anchor_a: [f32; 2],
+    pub anchor_b: [f32; 2],
+}
+
+#[cfg(feature = "rapier")]
+impl PartShape {
+    pub fn collider_shape(&self, scale: f32) -> Option<rapier2d::geometry::ColliderShape> {
+        use rapier2d::geometry::ColliderShape;
+        match self {
+            PartShape::Ball { radius } => Some(ColliderShape::ball(radius / scale)),
+            PartShape::Box { size } => Some(ColliderShape::cuboid(
+                size[0] / 2. / scale,
+                size[1] / 2. / scale,
+            )),
+            PartShape::Pivot => None,
+        }
+    }
+}
+
+#[cfg(feature = "rapier")]
+impl From<PartBody> for rapier2d::dynamics::RigidBodyType {
+    fn from(body: PartBody) -> Self {
+        match body {
+            PartBody::Static => Self::Static,
+            PartBody::Dynamic => Self::Dynamic,
+            PartBody::Kinematic => Self::KinematicPositionBased,
+        }
+    }
+}
+
+//Shared between the rendered and the headless simulation
+pub fn obstacle_parts(obstacle: &Obstacle) -> (Vec<ObstaclePart>, Vec<ObstacleJoint>) {
+    let part = |body, position, shape, density| ObstaclePart {
+        body,
+        position,
+        shape,
+        density,
+    };
+
+    match obstacle {
+        Obstacle::MovingPlatform { position, size, .. } => (
+            vec![part(
+                PartBody::Kinematic,
+                *position,
+                PartShape::Box { size: *size },
+                1.,
+            )],
+            vec![],
+        ),
+        Obstacle::Pendulum {
+            pivot,
+            length,
+            radius,
+            start_angle,
+        } => {
+            let angle = start_angle.to_radians();
+            let offset = [angle.sin() * length, -angle.cos() * length];
+            let bob = [pivot[0] + offset[0], pivot[1] + offset[1]];
+            (
+                vec![
+                    part(PartBody::Static, *pivot, PartShape::Pivot, 1.),
+                    part(
+                        PartBody::Dynamic,
+                        bob,
+                        PartShape::Ball { radius: *radius },
+                        5.,
+                    ),
+                ],
+                vec![ObstacleJoint {
+                    a: 0,
+                    b: 1,
+                    anchor_a: [0., 0.],
+                    anchor_b: [-offset[0], -offset[1]],
+                }],
+            )
+        }
+        Obstacle::Seesaw {
+            pivot,
+            length,
+            thickness,
+        } => (
+            vec![
+                part(PartBody::Static, *pivot, PartShape::Pivot, 1.),
+                part(
+                    PartBody::Dynamic,
+                    *pivot,
+                    PartShape::Box {
+                        size: [*length, *thickness],
+                    },
+                    1.,
+                ),
+            ],
+            vec![ObstacleJoint {
+                a: 0,
+                b: 1,
+                anchor_a: [0., 0.],
+                anchor_b: [0., 0.],
+            }],
+        ),
+        Obstacle::Boulder { position, radius } => (
+            vec![part(
+                PartBody::Dynamic,
+                *position,
+                PartShape::Ball { radius: *radius },
+                3.,
+            )],
+            vec![],
+        ),
+    }
+}
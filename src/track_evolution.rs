@@ -0,0 +1,219 @@
+use crate::{
+    fitness::GlobalFitnessMap,
+    genetics_simulator::{EvolutionParams, GeneticsSimulator},
+    genome::Genome,
+    track::generator::TrackGeneratorParams,
+};
+use dashmap::DashMap;
+use rand::seq::SliceRandom;
+use std::sync::Arc;
+
+use log::{debug, error, info, trace, warn}; //IMPORTANT or you won't get any output during tests!
+
+const DIFFICULTY_SCALE: f64 = 1000.; //Fitness of a track that is as hard as it gets, while still finishable
+
+//Second population, made of procedural tracks, that evolves alongside the vehicles (like POET).
+//A track is rewarded for being hard, but only if at least one vehicle could still finish it (minimal criterion).
+//The tracks are evolved by their own GeneticsSimulator, their difficulty is their fitness.
+pub struct TrackEvolver {
+    sim: GeneticsSimulator<TrackGeneratorParams>,
+    scores: Vec<Option<TrackScore>>, //None until the vehicles of this generation were evaluated on the track
+    statistics: Vec<TrackGenerationStatistics>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TrackScore {
+    pub difficulty: f64, //0 if every vehicle finished, 1 if no vehicle moved at all
+    pub finishable: bool,
+}
+
+impl TrackScore {
+    //Finishable tracks are better the harder they are. Tracks nobody finished are worse than any finishable one,
+    //and better the easier they are, so a population where nobody finishes anything gets easier.
+    pub fn fitness(&self) -> i64 {
+        let difficulty = (self.difficulty * DIFFICULTY_SCALE).round() as i64;
+        if self.finishable {
+            difficulty
+        } else {
+            -difficulty - 1
+        }
+    }
+}
+
+#[derive(new, Clone, Debug)]
+pub struct TrackGenerationStatistics {
+    pub avg_difficulty: f64,
+    pub max_difficulty: f64,   //Of the tracks that meet the minimal criterion
+    pub finishable_share: f64, //Fraction of the tracks at least one vehicle finished
+}
+
+//The simulator breeds in pairs, so odd sizes get rounded up
+pub fn population_size(requested: usize) -> usize {
+    (requested.max(2) + 1) / 2 * 2
+}
+
+impl TrackEvolver {
+    //Starts out with variants of the given track, just like TrackSet::generated_variants
+    pub fn new(base: &TrackGeneratorParams, size: usize) -> Self {
+        let population = (0..population_size(size) as u32)
+            .map(|i| TrackGeneratorParams {
+                seed: base.seed.wrapping_add(i),
+                ..base.clone()
+            })
+            .collect::<Vec<_>>();
+
+        TrackEvolver {
+            scores: vec![None; population.len()],
+            sim: GeneticsSimulator::from_population(population),
+            statistics: vec![],
+        }
+    }
+
+    pub fn population(&self) -> Vec<TrackGeneratorParams> {
+        self.sim.get_population_genomes()
+    }
+
+    pub fn scores(&self) -> &[Option<TrackScore>] {
+        &self.scores
+    }
+
+    pub fn generation(&self) -> usize {
+        self.sim.generation()
+    }
+
+    pub fn simulator(&self) -> &GeneticsSimulator<TrackGeneratorParams> {
+        &self.sim
+    }
+
+    pub fn get_generational_statistics(&self) -> &Vec<TrackGenerationStatistics> {
+        &self.statistics
+    }
+
+    //Fitness of every vehicle that was evaluated on the track, and the fitness needed to reach its finish line
+    pub fn set_fitness(&mut self, track: usize, vehicle_fitness: &[i64], finish_distance: i64) {
+        if track >= self.scores.len() || vehicle_fitness.is_empty() {
+            return;
+        }
+
+        let finish_distance = finish_distance.max(1);
+        let progress = vehicle_fitness
+            .iter()
+            .map(|f| (*f as f64 / finish_distance as f64).clamp(0., 1.))
+            .sum::<f64>()
+            / vehicle_fitness.len() as f64;
+
+        self.scores[track] = Some(TrackScore {
+            difficulty: 1. - progress,
+            finishable: vehicle_fitness.iter().any(|f| *f >= finish_distance),
+        });
+    }
+
+    //One generation of the track simulator, once every track was scored. Otherwise the tracks stay as they are.
+    pub fn step(&mut self, size: usize, mutations: u32) {
+        let scores = match self.scores.iter().copied().collect::<Option<Vec<_>>>() {
+            Some(scores) => scores,
+            None => {
+                warn!("not every track was evaluated, keeping the track population");
+                return;
+            }
+        };
+
+        let finishable = scores.iter().filter(|s| s.finishable).collect::<Vec<_>>();
+        self.statistics.push(TrackGenerationStatistics::new(
+            scores.iter().map(|s| s.difficulty).sum::<f64>() / scores.len() as f64,
+            finishable.iter().map(|s| s.difficulty).fold(0., f64::max),
+            finishable.len() as f64 / scores.len() as f64,
+        ));
+        info!(
+            "track generation {}: {}/{} tracks finishable",
+            self.generation() + 1,
+            finishable.len(),
+            scores.len()
+        );
+
+        //Only this generation's vehicles count, so every generation gets a fresh map
+        let map: GlobalFitnessMap<TrackGeneratorParams> = Arc::new(DashMap::new());
+        let generation = self.generation();
+        for (track, score) in self.population().into_iter().zip(&scores) {
+            map.entry(track)
+                .or_default()
+                .push(0, score.fitness(), generation);
+        }
+
+        let params = EvolutionParams {
+            tournament_k: 2,
+            mutation_amount: mutations as usize,
+            ..EvolutionParams::default()
+        };
+        self.sim.fill_in_fitness(&map, &params);
+        self.sim.step(&params);
+
+        let size = population_size(size);
+        let mut population = self.population();
+        if population.len() != size {
+            let mut rng = rand::thread_rng();
+            population.truncate(size);
+            while population.len() < size {
+                let mut child = population.choose(&mut rng).unwrap().clone();
+                child.mutate(mutations as usize);
+                population.push(child);
+            }
+            self.sim.overwrite_population(population);
+        }
+
+        self.scores = vec![None; size];
+    }
+}
+
+//For every track of the new population, the track of the old population it is identical to, if any.
+//Fitness measured on those tracks is still valid, see FitnessRecord::remap_tracks.
+pub fn unchanged_tracks(
+    old: &[TrackGeneratorParams],
+    new: &[TrackGeneratorParams],
+) -> Vec<Option<usize>> {
+    new.iter()
+        .map(|track| old.iter().position(|o| o == track))
+        .collect()
+}
+
+#[cfg(test)]
+#[test]
+fn test() {
+    let base = TrackGeneratorParams::default();
+    let mut evolver = TrackEvolver::new(&base, 3);
+    assert_eq!(evolver.population().len(), 4);
+
+    //Nothing was evaluated, so nothing changes
+    evolver.step(4, 1);
+    assert_eq!(evolver.generation(), 0);
+
+    //Tracks 1 and 2 are finishable, track 2 is the harder one
+    let finish = 1000;
+    evolver.set_fitness(0, &[100, 200], finish);
+    evolver.set_fitness(1, &[1000, 1000], finish);
+    evolver.set_fitness(2, &[1000, 0], finish);
+    evolver.set_fitness(3, &[0, 0], finish);
+    let fitness = evolver
+        .scores()
+        .iter()
+        .map(|s| s.unwrap().fitness())
+        .collect::<Vec<_>>();
+    assert_eq!(fitness, vec![-851, 0, 500, -1001]);
+
+    let old = evolver.population();
+    evolver.step(6, 1);
+    assert_eq!(evolver.generation(), 1);
+    assert_eq!(evolver.population().len(), 6);
+    assert!(evolver.scores().iter().all(|s| s.is_none()));
+    assert_eq!(
+        evolver.get_generational_statistics()[0].finishable_share,
+        0.5
+    );
+    assert_eq!(
+        evolver.simulator().get_generational_statistics()[0].max_fitness,
+        500.
+    );
+
+    let unchanged = unchanged_tracks(&old, &old[1..]);
+    assert_eq!(unchanged, vec![Some(1), Some(2), Some(3)]);
+}
@@ -1,8 +1,10 @@
+use crate::genome::Genome;
 use ndarray::Array2;
 use num_enum::TryFromPrimitive;
 use rand::distributions::WeightedIndex;
 use rand::prelude::*;
 use rand::seq::IteratorRandom;
+use serde::{Deserialize, Serialize};
 use std::{
     convert::TryFrom,
     fmt::{Display, Write},
@@ -12,7 +14,9 @@ use strum_macros::EnumIter;
 
 use log::{debug, error, info, trace, warn}; //IMPORTANT or you won't get any output during tests!
 
-#[derive(TryFromPrimitive, Clone, Debug, PartialEq, EnumIter, Eq, Hash, Copy)]
+#[derive(
+    TryFromPrimitive, Clone, Debug, PartialEq, EnumIter, Eq, Hash, Copy, Serialize, Deserialize,
+)]
 #[repr(u8)]
 pub enum Block {
     Air,
@@ -31,7 +35,7 @@ impl Display for Block {
     }
 }
 
-#[derive(PartialEq, Clone, Debug, Eq, Hash)]
+#[derive(PartialEq, Clone, Debug, Eq, Hash, Serialize, Deserialize)]
 
 pub struct Vehicle {
     pub blocks: Array2<Block>,
@@ -162,6 +166,64 @@ impl Vehicle {
     }
 }
 
+impl Genome for Vehicle {
+    fn random() -> Self {
+        Vehicle::new()
+    }
+
+    fn mutate(&mut self, amount: usize) {
+        Vehicle::mutate(self, amount)
+    }
+
+    fn crossover(&self, other: &Vehicle) -> (Vehicle, Vehicle) {
+        let (brother, sister, _) = self.crossover_at(other);
+        (brother, sister)
+    }
+
+    //The crossover point is a column, the left part comes from one parent and the right part from the other
+    fn crossover_at(&self, other: &Vehicle) -> (Vehicle, Vehicle, Option<usize>) {
+        let crossover_point = thread_rng().gen_range(1..VEHICLE_SHAPE.1);
+        let (brother, sister) = self.one_point_crossover(other, crossover_point);
+        (brother, sister, Some(crossover_point))
+    }
+
+    fn genes(&self) -> Vec<u32> {
+        self.blocks.iter().map(|block| *block as u32).collect()
+    }
+}
+
+pub const GRID_CELL_SIZE: (f32, f32) = (60., 60.); //how big every cell should be in pixels
+
+//Physical properties of a single block, shared between the rendered and the headless simulation
+pub struct BlockPhysics {
+    pub scale: f32, //Sprite scale, the collider is sized to match the texture
+    pub radius: f32,
+    pub friction: f32,
+}
+
+pub fn block_physics(block: &Block) -> Option<BlockPhysics> {
+    let (texture_size, friction) = match block {
+        Block::Air => return None,
+        Block::Panel => (100., 0.1), //metalPanel.png
+        Block::Wheel => (128., 0.6), //saw.png
+    };
+
+    let scale = GRID_CELL_SIZE.0 / texture_size;
+    Some(BlockPhysics {
+        scale,
+        radius: scale / 2.,
+        friction,
+    })
+}
+
+//Position of the block at (y, x) relative to the vehicle's center, in physics units
+pub fn block_position(x: usize, y: usize, grid_size: &[usize], sim_scale: f32) -> [f32; 2] {
+    [
+        (x as f32 - grid_size[0] as f32 / 2.) * GRID_CELL_SIZE.0 / sim_scale,
+        -(y as f32 - grid_size[1] as f32 / 2.) * GRID_CELL_SIZE.1 / sim_scale,
+    ]
+}
+
 #[cfg(test)]
 #[test]
 fn test() {
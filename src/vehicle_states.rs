@@ -1,5 +1,10 @@
-use crate::vehicle::Vehicle;
-use bevy::prelude::Color;
+use crate::{
+    novelty::{Behavior, BehaviorRecorder},
+    track::TrackMetadata,
+    vehicle::Vehicle,
+};
+use rand::Rng;
+use std::collections::HashMap;
 use std::fmt::*;
 
 use log::{debug, error, info, trace, warn}; //IMPORTANT or you won't get any output during tests!
@@ -7,8 +12,6 @@ use log::{debug, error, info, trace, warn}; //IMPORTANT or you won't get any out
 #[derive(Clone)]
 pub struct VehicleStates(Vec<VehicleState>);
 
-const FITNESS_FINISH_THRESHOLD: i64 = 14400; //If fitness goes above this value, the vehicle reached the finish flag
-
 impl VehicleStates {
     #[allow(dead_code)]
     pub fn new() -> Self {
@@ -20,8 +23,124 @@ impl VehicleStates {
         VehicleStates(v)
     }
 
-    pub fn from(pop: Vec<Vehicle>) -> Self {
-        VehicleStates(pop.into_iter().map(VehicleState::from).collect())
+    //Every vehicle gets `evaluations` states on every track, so it gets simulated that many times per track
+    pub fn from(pop: Vec<Vehicle>, evaluations: u32, track_count: usize) -> Self {
+        VehicleStates(
+            pop.into_iter()
+                .flat_map(|vehicle| {
+                    (0..track_count).flat_map(move |track| {
+                        let vehicle = vehicle.clone();
+                        (0..evaluations).map(move |evaluation| VehicleState {
+                            evaluation,
+                            track,
+                            ..VehicleState::from(vehicle.clone())
+                        })
+                    })
+                })
+                .collect(),
+        )
+    }
+
+    //Like from(), but vehicles whose fitness is already known are marked as done right away
+    pub fn from_cached(
+        pop: Vec<(Vehicle, Option<i64>)>,
+        evaluations: u32,
+        track_count: usize,
+        track: &TrackMetadata, //Only used to show whether cached vehicles reached the finish
+    ) -> Self {
+        let mut states = vec![];
+        for (vehicle, fitness) in pop {
+            if let Some(fitness) = fitness {
+                states.push(VehicleState {
+                    fitness,
+                    status: VehicleStatus::Done,
+                    reached_finish: fitness >= track.finish_distance(),
+                    checkpoints_reached: track.checkpoints_reached(fitness),
+                    from_cache: true,
+                    ..VehicleState::from(vehicle)
+                });
+            } else {
+                states.extend(VehicleStates::from(vec![vehicle], evaluations, track_count).0);
+            }
+        }
+        VehicleStates(states)
+    }
+
+    //One set of states per island. The islands take turns, so every batch of vehicles has some of every island.
+    pub fn interleave_islands(islands: Vec<VehicleStates>) -> Self {
+        let mut islands = islands
+            .into_iter()
+            .enumerate()
+            .map(|(island, states)| {
+                states.0.into_iter().map(move |state| VehicleState {
+                    island: Some(island),
+                    ..state
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut states = vec![];
+        loop {
+            let before = states.len();
+            states.extend(islands.iter_mut().filter_map(|island| island.next()));
+            if states.len() == before {
+                break;
+            }
+        }
+        VehicleStates(states)
+    }
+
+    //Simulates an extra vehicle on the given track, as soon as there's room for it
+    pub fn request(&mut self, vehicle: Vehicle, track: usize) {
+        self.0.push(VehicleState {
+            track,
+            requested: true,
+            ..VehicleState::from(vehicle)
+        });
+    }
+
+    //Vehicles whose states are all done, and that weren't returned before. Used by the steady-state GA, which
+    //breeds offspring as soon as vehicles finish. Requested vehicles aren't part of the population, so they're skipped.
+    pub fn take_finished(&mut self) -> Vec<Vehicle> {
+        let mut by_vehicle: HashMap<Vehicle, Vec<usize>> = HashMap::new();
+        for (i, state) in self.0.iter().enumerate() {
+            if !state.requested && !state.reported {
+                by_vehicle.entry(state.vehicle.clone()).or_default().push(i);
+            }
+        }
+
+        let mut finished = vec![];
+        for (vehicle, indices) in by_vehicle {
+            if indices
+                .iter()
+                .all(|i| self.0[*i].status == VehicleStatus::Done)
+            {
+                for i in indices {
+                    self.0[i].reported = true;
+                }
+                finished.push(vehicle);
+            }
+        }
+        finished
+    }
+
+    //Adds more vehicles to simulate. They take the place of states returned by take_finished, so the list doesn't
+    //grow forever, and the IDs of vehicles that are still running stay the same.
+    pub fn add(&mut self, states: VehicleStates) {
+        let mut free = self
+            .0
+            .iter()
+            .enumerate()
+            .filter(|(_, state)| state.reported)
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>()
+            .into_iter();
+        for state in states.0 {
+            match free.next() {
+                Some(i) => self.0[i] = state,
+                None => self.0.push(state),
+            }
+        }
     }
 
     pub fn all_done(&self) -> bool {
@@ -38,50 +157,130 @@ impl VehicleStates {
         &mut self.0
     }
 
-    pub fn pop_vehicles(&mut self, limit: usize) -> Vec<(Vehicle, VehicleID, Color)> {
-        //Finds all vehicle state that is Pending, turns it to Running, and returns its inner Vehicle.
+    pub fn pop_vehicles(
+        &mut self,
+        limit: usize,
+        spawn_jitter: f32,
+        track: Option<usize>,
+    ) -> Vec<PoppedVehicle> {
+        let slots = (0..limit).collect::<Vec<_>>();
+        self.pop_into_slots(&slots, limit, spawn_jitter, track)
+    }
+
+    //Like pop_vehicles, but only fills the given batch slots, e.g. the ones of vehicles that just finished.
+    //The colors are picked out of batch_size slots, so refilled slots keep their color.
+    pub fn pop_into_slots(
+        &mut self,
+        slots: &[usize],
+        batch_size: usize,
+        spawn_jitter: f32,
+        track: Option<usize>,
+    ) -> Vec<PoppedVehicle> {
+        //Finds all vehicle state that is Pending (on the given track, if any), turns it to Running, and returns its inner Vehicle.
         //Returns nothing if no pending vehicles exist
 
         let gradient = colorous::TURBO;
+        let mut rng = rand::thread_rng();
 
-        self.0
-            .iter_mut()
+        //Requested vehicles go first, the rest keeps its order
+        let mut pending = self
+            .0
+            .iter()
             .enumerate()
             .filter(|(_, state)| state.status == VehicleStatus::Pending)
-            .take(limit)
-            .enumerate()
-            .map(|(color_idx, (i, state))| {
+            .filter(|(_, state)| track.map_or(true, |track| state.track == track))
+            .map(|(i, state)| (i, state.requested))
+            .collect::<Vec<_>>();
+        pending.sort_by_key(|(_, requested)| !requested);
+
+        pending
+            .into_iter()
+            .zip(slots)
+            .map(|((i, _), slot)| {
+                let state = &mut self.0[i];
                 state.status = VehicleStatus::Running; //Set first n Pending vehicles to Running
 
-                let color = gradient.eval_rational(color_idx, limit);
-                let mut color = Color::rgb(
+                //The first evaluation always spawns at the same place, repeated evaluations get jittered
+                state.spawn_offset = if state.evaluation > 0 && spawn_jitter > 0. {
+                    rng.gen_range(-spawn_jitter..spawn_jitter)
+                } else {
+                    0.
+                };
+
+                let color = gradient.eval_rational(*slot, batch_size);
+                let mut color = [
                     color.r as f32 / 255.,
                     color.g as f32 / 255.,
                     color.b as f32 / 255.,
-                );
+                ];
 
-                if limit == 1 {
+                if batch_size == 1 {
                     //If only 1 vehicle popped, use white
-                    color = Color::WHITE;
+                    color = [1., 1., 1.];
                 }
 
-                (state.vehicle.clone(), VehicleID(i), color) //And return them
+                //And return them
+                PoppedVehicle {
+                    vehicle: state.vehicle.clone(),
+                    id: VehicleID(i),
+                    color,
+                    batch_slot: *slot,
+                    spawn_offset: state.spawn_offset,
+                    track: state.track,
+                }
             })
             .collect()
     }
 
-    pub fn set_fitness(&mut self, VehicleID(i): VehicleID, new_fitness: i64, fell_apart: bool) {
+    pub fn get_spawn_offset(&self, VehicleID(i): VehicleID) -> f32 {
+        self.0[i].spawn_offset
+    }
+
+    //Track of the first pending vehicle, the rendered simulation switches to it once the current track is done
+    pub fn next_pending_track(&self) -> Option<usize> {
+        self.0
+            .iter()
+            .find(|state| state.status == VehicleStatus::Pending)
+            .map(|state| state.track)
+    }
+
+    pub fn set_fitness(
+        &mut self,
+        VehicleID(i): VehicleID,
+        new_fitness: i64,
+        fell_apart: bool,
+        track: &TrackMetadata,
+    ) {
         let vehicle = &mut self.0[i];
         vehicle.fitness = new_fitness;
 
-        if new_fitness >= FITNESS_FINISH_THRESHOLD {
+        if new_fitness >= track.finish_distance() {
             vehicle.reached_finish = true;
         }
+        //Checkpoints stay reached, even if the vehicle rolls back afterwards
+        vehicle.checkpoints_reached = vehicle
+            .checkpoints_reached
+            .max(track.checkpoints_reached(new_fitness));
 
         vehicle.fell_apart = fell_apart;
     }
 
-    pub fn finalize_vehicle(&mut self, VehicleID(i): VehicleID) -> (Vehicle, i64) {
+    //Distance and height in pixels from the spawn point, for novelty search
+    pub fn observe_behavior(
+        &mut self,
+        VehicleID(i): VehicleID,
+        distance: f32,
+        height: f32,
+        wheels_touching: usize,
+        wheels: usize,
+    ) {
+        self.0[i]
+            .behavior
+            .observe(distance, height, wheels_touching, wheels);
+    }
+
+    //Returns the vehicle, the track it was evaluated on, its fitness and its behavior
+    pub fn finalize_vehicle(&mut self, VehicleID(i): VehicleID) -> (Vehicle, usize, i64, Behavior) {
         let mut new_status = None;
         let state = &mut self.0[i];
 
@@ -102,42 +301,79 @@ impl VehicleStates {
         }
 
         let fitness = state.fitness;
+        let behavior = state.behavior.finish(state.fell_apart);
 
-        (state.vehicle.clone(), fitness)
+        (state.vehicle.clone(), state.track, fitness, behavior)
     }
 }
 
+pub struct PoppedVehicle {
+    pub vehicle: Vehicle,
+    pub id: VehicleID,
+    pub color: [f32; 3],   //RGB, between 0 and 1
+    pub batch_slot: usize, //Index within the batch of vehicles that is simulated at once
+    pub spawn_offset: f32, //Horizontal offset from the spawn point, in pixels
+    pub track: usize,      //Index in the track set
+}
+
 #[derive(PartialEq, Debug, Clone)]
 
 pub struct VehicleState {
     pub vehicle: Vehicle,
+    pub evaluation: u32, //Which evaluation of this vehicle this is, starting at 0
+    pub track: usize,    //Index in the track set, always 0 if it's disabled
+    pub spawn_offset: f32,
     pub fitness: i64,
     pub status: VehicleStatus,
     pub reached_finish: bool,
+    pub checkpoints_reached: usize,
     pub is_camera_target: bool,
     pub fell_apart: bool,
+    pub from_cache: bool,      //Fitness was looked up instead of simulated
+    pub island: Option<usize>, //None unless the island model is on
+    pub behavior: BehaviorRecorder,
+    pub requested: bool, //Asked for in the GUI instead of being part of the population, e.g. an elite
+    pub reported: bool,  //Already returned by take_finished
 }
 
 impl VehicleState {
     pub fn new() -> Self {
         VehicleState {
             vehicle: Vehicle::new(),
+            evaluation: 0,
+            track: 0,
+            spawn_offset: 0.,
             fitness: i64::MIN,
             status: VehicleStatus::Pending,
             reached_finish: false,
+            checkpoints_reached: 0,
             is_camera_target: false,
             fell_apart: false,
+            from_cache: false,
+            island: None,
+            behavior: BehaviorRecorder::default(),
+            requested: false,
+            reported: false,
         }
     }
 
     pub fn from(v: Vehicle) -> Self {
         VehicleState {
             vehicle: v,
+            evaluation: 0,
+            track: 0,
+            spawn_offset: 0.,
             fitness: i64::MIN,
             status: VehicleStatus::Pending,
             reached_finish: false,
+            checkpoints_reached: 0,
             is_camera_target: false,
             fell_apart: false,
+            from_cache: false,
+            island: None,
+            behavior: BehaviorRecorder::default(),
+            requested: false,
+            reported: false,
         }
     }
 }
@@ -157,9 +393,16 @@ impl Display for VehicleState {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         let fitness = self.fitness;
 
-        let finish_icon = if self.reached_finish { "🏁" } else { " " };
+        let finish_icon = if self.reached_finish {
+            "🏁".to_owned()
+        } else if self.checkpoints_reached > 0 {
+            format!("⛳{}", self.checkpoints_reached)
+        } else {
+            " ".to_owned()
+        };
         let camera_icon = if self.is_camera_target { "🔆" } else { " " };
         let fell_apart_icon = if self.fell_apart { "❌" } else { " " }; //💀
+        let cache_icon = if self.from_cache { "💾" } else { " " };
 
         match self.status {
             VehicleStatus::Pending => {
@@ -175,10 +418,34 @@ impl Display for VehicleState {
             VehicleStatus::Done => {
                 write!(
                     f,
-                    "✅ fitness = {:#5} {} {} {}",
-                    fitness, finish_icon, fell_apart_icon, camera_icon
+                    "✅ fitness = {:#5} {} {} {} {}",
+                    fitness, finish_icon, fell_apart_icon, camera_icon, cache_icon
                 )
             }
         }
     }
 }
+
+#[cfg(test)]
+#[test]
+fn test() {
+    let mut states = VehicleStates::from(vec![Vehicle::new(); 5], 1, 1);
+    let batch = states.pop_vehicles(3, 0., None);
+    assert_eq!(
+        batch.iter().map(|p| p.batch_slot).collect::<Vec<_>>(),
+        vec![0, 1, 2]
+    );
+
+    //The vehicle in slot 1 finished, only that slot gets refilled, with the same color
+    states.finalize_vehicle(batch[1].id);
+    let refill = states.pop_into_slots(&[1], 3, 0., None);
+    assert_eq!(refill.len(), 1);
+    assert_eq!(refill[0].batch_slot, 1);
+    assert_eq!(refill[0].id, VehicleID(3));
+    assert_eq!(refill[0].color, batch[1].color);
+
+    //More free slots than pending vehicles
+    let rest = states.pop_into_slots(&[0, 2], 3, 0., None);
+    assert_eq!(rest.len(), 1);
+    assert!(states.pop_into_slots(&[0], 3, 0., None).is_empty());
+}